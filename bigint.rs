@@ -0,0 +1,121 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// Unsigned integer of any width, used for integer literals so that no value
+// is ever rounded before type checking decides whether it fits. Limbs are
+// little endian and never have trailing zero limbs, so zero is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigUint {
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+
+    pub fn zero() -> Self {
+        return Self {
+            limbs: Vec::new(),
+        };
+    }
+
+    pub fn from_u128(value: u128) -> Self {
+        let mut result = Self {
+            limbs: vec![value as u64, (value >> 64) as u64],
+        };
+        result.normalize();
+        return result;
+    }
+
+    // Parses `digits` in `radix`, skipping `_` separators. The caller
+    // guarantees every other character is a valid digit.
+    pub fn from_digits(digits: &str, radix: u32) -> Self {
+        let mut result = Self::zero();
+        for c in digits.chars().filter(|c| *c != '_') {
+            result.mul_add(radix as u64, c.to_digit(radix).unwrap() as u64);
+        }
+        return result;
+    }
+
+    fn normalize(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+
+    // self = self * mul + add
+    fn mul_add(&mut self, mul: u64, add: u64) {
+        let mut carry = add as u128;
+        for limb in self.limbs.iter_mut() {
+            let product = (*limb as u128) * (mul as u128) + carry;
+            *limb = product as u64;
+            carry = product >> 64;
+        }
+        if carry != 0 {
+            self.limbs.push(carry as u64);
+        }
+        self.normalize();
+    }
+
+    // Returns (self / divisor, self % divisor).
+    fn div_rem(&self, divisor: u64) -> (BigUint, u64) {
+        let mut quotient = vec![0u64; self.limbs.len()];
+        let mut rem: u128 = 0;
+        for (i, limb) in self.limbs.iter().enumerate().rev() {
+            let current = (rem << 64) | (*limb as u128);
+            quotient[i] = (current / divisor as u128) as u64;
+            rem = current % divisor as u128;
+        }
+        let mut result = BigUint { limbs: quotient };
+        result.normalize();
+        return (result, rem as u64);
+    }
+
+    pub fn is_zero(&self) -> bool {
+        return self.limbs.is_empty();
+    }
+
+    pub fn to_u128(&self) -> Option<u128> {
+        match self.limbs.as_slice() {
+            [] => Some(0),
+            [low] => Some(*low as u128),
+            [low, high] => Some(((*high as u128) << 64) | *low as u128),
+            _ => None,
+        }
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.limbs.len().cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()));
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off 19 decimal digits at a time, the most that fits in a u64.
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut chunks = Vec::new();
+        let mut rest = self.clone();
+        while !rest.is_zero() {
+            let (quotient, rem) = rest.div_rem(CHUNK);
+            chunks.push(rem);
+            rest = quotient;
+        }
+
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:019}", chunk)?;
+        }
+        return Ok(());
+    }
+}
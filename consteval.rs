@@ -0,0 +1,239 @@
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::ASTNode;
+use crate::span::Span;
+use crate::types::Type;
+
+// Computes the value of a type checked expression at compile time, for the
+// initializers of constants and for array lengths. Only literals, other
+// constants, operators and casts can be evaluated. Arithmetic that does not
+// fit in the type of the expression is an error rather than wrapping around,
+// a cast truncates like it would at run time.
+//
+// A pointer is evaluated to its address, so `0x1000 as *mut u8` is a
+// constant too.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Int(i128),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// `Err(None)` when the problem was already reported, by the type checker or
+// while evaluating another constant.
+pub type EvalResult = Result<Value, Option<Diagnostic>>;
+
+// What the evaluator needs to know about the rest of the program.
+pub trait Env {
+    // Type the type checker gave to the expression at `span`.
+    fn type_of(&self, span: Span) -> Type;
+
+    // Value of the constant named at `span`, or `None` if the name is not a
+    // constant.
+    fn constant(&mut self, span: Span) -> Option<EvalResult>;
+}
+
+pub fn eval(node: &ASTNode, env: &mut impl Env) -> EvalResult {
+    match node {
+        // Kept as written: the type checker reported literals too large for
+        // their type, except that `-128` is an `i8` made of `128`.
+        ASTNode::IntLiteral(value, _, _) => return Ok(Value::Int(value.to_u128().unwrap_or(0) as i128)),
+
+        ASTNode::CharLiteral(c, _) => return Ok(Value::Int(*c as i128)),
+
+        ASTNode::BoolNode(value, _) => return Ok(Value::Bool(*value)),
+
+        ASTNode::Identifier(name, span) => match env.constant(*span) {
+            Some(result) => return result,
+            None => {
+                return Err(Some(
+                    Diagnostic::error("E0804", format!("`{}` is not a constant", name), *span)
+                        .with_label("its value is only known at run time")
+                        .with_note("constant expressions can only use literals, other constants, operators and casts"),
+                ));
+            }
+        },
+
+        ASTNode::UnaryOpNode { op, operand, span } => {
+            let value = eval(operand, env)?;
+            return unary(op, value, &env.type_of(*span), *span);
+        }
+
+        ASTNode::BinOpNode { op, left, right, span } => {
+            let lhs = eval(left, env)?;
+
+            // The right side is not evaluated when it cannot change the
+            // result, like at run time.
+            match (op.as_str(), lhs) {
+                ("and", Value::Bool(false)) | ("or", Value::Bool(true)) => return Ok(lhs),
+                _ => {}
+            }
+
+            let rhs = eval(right, env)?;
+            return binary(op, lhs, rhs, &env.type_of(*span), *span, right.span());
+        }
+
+        ASTNode::CastNode { value, span, .. } => {
+            let value = match eval(value, env)? {
+                Value::Int(value) => value,
+                Value::Bool(value) => value as i128,
+            };
+            return Ok(Value::Int(wrap(value, &env.type_of(*span))?));
+        }
+
+        _ => {
+            let what = match node {
+                ASTNode::FuncCall { .. } => "function calls",
+                ASTNode::DerefNode { .. } => "reads through a pointer",
+                ASTNode::AddrOfNode { .. } => "addresses of variables",
+                ASTNode::StrLiteral(..) | ASTNode::ArrayLiteral { .. } | ASTNode::ArrayRepeat { .. } => "arrays",
+                ASTNode::IndexNode { .. } => "array elements",
+                _ => "expressions like this",
+            };
+            return Err(Some(
+                Diagnostic::error("E0804", "expression cannot be evaluated at compile time", node.span())
+                    .with_label(format!("{} are not constant", what))
+                    .with_note("constant expressions can only use literals, other constants, operators and casts"),
+            ));
+        }
+    }
+}
+
+// The constant declared at `decl` is used at `span` while its own value is
+// being computed.
+pub fn cycle(name: &str, decl: Span, span: Span) -> Diagnostic {
+    return Diagnostic::error("E0805", format!("constant `{}` is defined in terms of itself", name), decl)
+        .with_label("computing this value never ends")
+        .with_secondary(span, format!("`{}` is used again here", name));
+}
+
+// Smallest and largest value of an integer or pointer type.
+fn range(ty: &Type) -> Option<(i128, i128)> {
+    match ty {
+        Type::Int(int) if int.is_signed() => Some((-(int.max() as i128) - 1, int.max() as i128)),
+        Type::Int(int) => Some((0, int.max() as i128)),
+        Type::Pointer { .. } => Some((0, u64::MAX as i128)),
+        _ => None,
+    }
+}
+
+fn bits(ty: &Type) -> u32 {
+    match ty {
+        Type::Int(int) => int.bits(),
+        _ => 64,
+    }
+}
+
+// Truncates `value` to the width of `ty`, as a cast does.
+fn wrap(value: i128, ty: &Type) -> Result<i128, Option<Diagnostic>> {
+    let (min, _) = range(ty).ok_or(None)?;
+    let bits = bits(ty);
+    let value = value & ((1i128 << bits) - 1);
+
+    if min < 0 && value >= 1i128 << (bits - 1) {
+        return Ok(value - (1i128 << bits));
+    }
+    return Ok(value);
+}
+
+// Checks that `value`, computed by `expr` at `span`, fits in `ty`.
+fn fit(value: Option<i128>, expr: String, ty: &Type, span: Span) -> EvalResult {
+    let (min, max) = range(ty).ok_or(None)?;
+
+    match value {
+        Some(value) if value >= min && value <= max => return Ok(Value::Int(value)),
+        _ => {
+            return Err(Some(
+                Diagnostic::error("E0801", "arithmetic overflow in constant expression", span)
+                    .with_label(format!("`{}` does not fit in `{}`", expr, ty))
+                    .with_note(format!("the range of `{}` is {}..={}", ty, min, max)),
+            ));
+        }
+    }
+}
+
+fn unary(op: &str, value: Value, ty: &Type, span: Span) -> EvalResult {
+    match (op, value) {
+        ("-", Value::Int(value)) => return fit(value.checked_neg(), format!("-{}", value), ty, span),
+        ("~", Value::Int(value)) => return Ok(Value::Int(wrap(!value, ty)?)),
+        ("!", Value::Bool(value)) => return Ok(Value::Bool(!value)),
+        _ => return Err(None),
+    }
+}
+
+// `ty` is the type of the result, and also that of the left operand unless
+// `op` compares. `right` is where the right operand is written.
+fn binary(op: &str, lhs: Value, rhs: Value, ty: &Type, span: Span, right: Span) -> EvalResult {
+    let (a, b) = match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => (a, b),
+        (Value::Bool(a), Value::Bool(b)) => match op {
+            "and" => return Ok(Value::Bool(a && b)),
+            "or" => return Ok(Value::Bool(a || b)),
+            "==" => return Ok(Value::Bool(a == b)),
+            "!=" => return Ok(Value::Bool(a != b)),
+            _ => return Err(None),
+        },
+        _ => return Err(None),
+    };
+    let expr = format!("{} {} {}", a, op, b);
+
+    match op {
+        "+" => return fit(a.checked_add(b), expr, ty, span),
+        "-" => return fit(a.checked_sub(b), expr, ty, span),
+        "*" => return fit(a.checked_mul(b), expr, ty, span),
+
+        "/" | "%" if b == 0 => {
+            let label = if op == "/" {
+                format!("attempt to divide `{}` by zero", a)
+            } else {
+                format!("attempt to calculate the remainder of `{}` with a divisor of zero", a)
+            };
+            return Err(Some(
+                Diagnostic::error("E0802", "division by zero in constant expression", span)
+                    .with_label(label)
+                    .with_secondary(right, "this is zero"),
+            ));
+        }
+        // Only `MIN / -1` can overflow.
+        "/" => return fit(a.checked_div(b), expr, ty, span),
+        "%" => return fit(a.checked_rem(b), expr, ty, span),
+
+        "&" => return Ok(Value::Int(a & b)),
+        "|" => return Ok(Value::Int(a | b)),
+        "^" => return Ok(Value::Int(a ^ b)),
+
+        "<<" | ">>" => {
+            let bits = bits(ty);
+            if b < 0 || b >= bits as i128 {
+                return Err(Some(
+                    Diagnostic::error("E0803", "shift amount out of range in constant expression", span)
+                        .with_label(format!("cannot shift a `{}` by {}", ty, b))
+                        .with_secondary(right, format!("this is {}", b))
+                        .with_note(format!("a `{}` can only be shifted by 0..{}", ty, bits)),
+                ));
+            }
+            // Bits shifted out are lost, as at run time.
+            let value = if op == "<<" { a << b } else { a >> b };
+            return Ok(Value::Int(wrap(value, ty)?));
+        }
+
+        "==" => return Ok(Value::Bool(a == b)),
+        "!=" => return Ok(Value::Bool(a != b)),
+        "<" => return Ok(Value::Bool(a < b)),
+        ">" => return Ok(Value::Bool(a > b)),
+        "<=" => return Ok(Value::Bool(a <= b)),
+        ">=" => return Ok(Value::Bool(a >= b)),
+
+        _ => return Err(None),
+    }
+}
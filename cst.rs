@@ -0,0 +1,366 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::lexer::{Token, TokenType};
+use crate::span::Span;
+
+// Lossless syntax tree. The green tree is immutable and only knows kinds,
+// lengths and the span of each token, token text is a slice of the source.
+// The red tree (`SyntaxNode`) wraps it with absolute offsets, computed as
+// the tree is walked, and tokens know the node they sit in. Every byte of
+// the source sits in exactly one token, so printing the tree gives the
+// source back unchanged.
+
+// Interior node kinds. Leaves are tokens and keep their `TokenType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Root, Error,
+
+    VarDec, ConstDecl, TypeDecl, FuncDef, If, ElifClause, ElseClause, Loop,
+    Break, Return, Assign, ExprStmt,
+
+    Block, ParamList, Param, ArgList, Condition, Type,
+
+    Literal, Name, Paren, UnaryOp, BinOp, Cast, Deref, AddrOf,
+    ArrayLiteral, ArrayRepeat, Index, Call,
+}
+
+#[derive(Debug)]
+pub struct GreenToken {
+    kind: TokenType,
+    span: Span,
+}
+
+#[derive(Debug)]
+pub struct GreenNode {
+    kind: NodeKind,
+    len: usize,
+    children: Vec<GreenElement>,
+}
+
+#[derive(Debug, Clone)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len,
+            GreenElement::Token(token) => token.span.end - token.span.start,
+        }
+    }
+}
+
+impl GreenNode {
+    fn new(kind: NodeKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(|child| child.len()).sum();
+        return Self { kind, len, children };
+    }
+
+    // Writes the text of every token under the node, in order.
+    fn write(&self, src: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => node.write(src, f)?,
+                GreenElement::Token(token) => f.write_str(&src[token.span.start..token.span.end])?,
+            }
+        }
+        return Ok(());
+    }
+
+    // Span of the first token under the node that `keep` accepts, or of the
+    // last one with `from_end`. Only the tokens before it are visited.
+    fn find_token(&self, keep: &dyn Fn(TokenType) -> bool, from_end: bool) -> Option<Span> {
+        let found = |child: &GreenElement| match child {
+            GreenElement::Node(node) => node.find_token(keep, from_end),
+            GreenElement::Token(token) if keep(token.kind) => Some(token.span),
+            GreenElement::Token(_) => None,
+        };
+
+        if from_end {
+            return self.children.iter().rev().find_map(found);
+        }
+        return self.children.iter().find_map(found);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    src: Rc<str>,
+    offset: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+
+    fn new_root(green: GreenNode, src: &str) -> Self {
+        return SyntaxNode(Rc::new(NodeData {
+            green: Rc::new(green),
+            src: src.into(),
+            offset: 0,
+        }));
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        return self.0.green.kind;
+    }
+
+    // Byte range of the node in the source.
+    pub fn range(&self) -> (usize, usize) {
+        return (self.0.offset, self.0.offset + self.0.green.len);
+    }
+
+    // Child nodes and tokens, trivia included, in source order.
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = Vec::new();
+
+        for child in &self.0.green.children {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    src: self.0.src.clone(),
+                    offset,
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                }),
+            };
+            offset += child.len();
+            children.push(element);
+        }
+
+        return children;
+    }
+
+    // Span of the first token under the node that `keep` accepts.
+    pub fn first_token_span(&self, keep: impl Fn(TokenType) -> bool) -> Option<Span> {
+        return self.0.green.find_token(&keep, false);
+    }
+
+    // Span of the last token under the node that `keep` accepts.
+    pub fn last_token_span(&self, keep: impl Fn(TokenType) -> bool) -> Option<Span> {
+        return self.0.green.find_token(&keep, true);
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return self.0.green.write(&self.0.src, f);
+    }
+}
+
+impl SyntaxToken {
+
+    pub fn kind(&self) -> TokenType {
+        return self.green.kind;
+    }
+
+    pub fn text(&self) -> &str {
+        return &self.parent.0.src[self.green.span.start..self.green.span.end];
+    }
+
+    pub fn span(&self) -> Span {
+        return self.green.span;
+    }
+
+    pub fn range(&self) -> (usize, usize) {
+        return (self.green.span.start, self.green.span.end);
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        return self.parent.clone();
+    }
+}
+
+// Position in the builder's list of finished children, taken before a
+// production starts so its node can be wrapped around what it consumed once
+// the parser knows it succeeded.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint(usize);
+
+// Builds the green tree while the parser runs. Significant tokens arrive in
+// the order the parser consumes them. Trivia, and any text the lexer
+// rejected, is slotted in before the next significant token so that every
+// byte of the source ends up in the tree. Like the lexer, the builder tracks
+// the line and column as it moves through the source, so text the lexer
+// rejected gets a span too.
+pub struct Builder<'a> {
+    src: &'a str,
+    file: usize,
+    trivia: Vec<Token<'a>>,
+    next_trivia: usize,
+    pos: usize,
+    line: usize,
+    column: usize,
+    children: Vec<GreenElement>,
+}
+
+impl<'a> Builder<'a> {
+
+    pub fn new(src: &'a str, file: usize, trivia: Vec<Token<'a>>) -> Self {
+        return Self {
+            src,
+            file,
+            trivia,
+            next_trivia: 0,
+            pos: 0,
+            line: 1,
+            column: 1,
+            children: Vec::new(),
+        };
+    }
+
+    fn leaf(&mut self, kind: TokenType, end: usize) {
+        let span = Span {
+            file: self.file,
+            line: self.line,
+            column: self.column,
+            start: self.pos,
+            end,
+        };
+
+        for c in self.src[self.pos..end].chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+
+        self.children.push(GreenElement::Token(Rc::new(GreenToken { kind, span })));
+        self.pos = end;
+    }
+
+    // Covers the source up to `offset` with trivia tokens. Text no token
+    // covers becomes an `Unknown` token.
+    fn advance_to(&mut self, offset: usize) {
+        while let Some(token) = self.trivia.get(self.next_trivia) {
+            if token.span.start >= offset {
+                break;
+            }
+            let (kind, start, end) = (token.ttype, token.span.start, token.span.end);
+            if start > self.pos {
+                self.leaf(TokenType::Unknown, start);
+            }
+            self.leaf(kind, end);
+            self.next_trivia += 1;
+        }
+
+        if offset > self.pos {
+            self.leaf(TokenType::Unknown, offset);
+        }
+    }
+
+    pub fn token(&mut self, token: &Token) {
+        self.advance_to(token.span.start);
+        self.leaf(token.ttype, token.span.end);
+    }
+
+    // `next` is the start of the next significant token. Trivia before it
+    // is flushed first so it stays outside the node about to start.
+    pub fn checkpoint(&mut self, next: usize) -> Checkpoint {
+        self.advance_to(next);
+        return Checkpoint(self.children.len());
+    }
+
+    // Wraps everything added since `checkpoint` in a node of `kind`.
+    pub fn wrap(&mut self, checkpoint: Checkpoint, kind: NodeKind) {
+        let children = self.children.split_off(checkpoint.0);
+        self.children.push(GreenElement::Node(Rc::new(GreenNode::new(kind, children))));
+    }
+
+    pub fn finish(mut self) -> SyntaxNode {
+        self.advance_to(self.src.len());
+        let children = std::mem::take(&mut self.children);
+        return SyntaxNode::new_root(GreenNode::new(NodeKind::Root, children), self.src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyntaxElement, SyntaxNode};
+    use crate::lexer::{Lexer, TokenType};
+    use crate::parser::Parser;
+    use crate::span::Span;
+
+    // Trees for `src` built with the trivia lexed and with it left to the
+    // builder. Tokens the lexer rejected are dropped, like the driver does
+    // before it gives up.
+    fn trees(src: &str) -> Vec<SyntaxNode> {
+        return [Lexer::new(src, 0), Lexer::with_trivia(src, 0)]
+            .into_iter()
+            .map(|lexer| {
+                let tokens = lexer.filter_map(Result::ok).collect();
+                let (tree, _, _) = Parser::new(tokens, src).parse_program();
+                tree
+            })
+            .collect();
+    }
+
+    fn token_spans(node: &SyntaxNode, out: &mut Vec<(TokenType, Span)>) {
+        for child in node.children() {
+            match child {
+                SyntaxElement::Node(node) => token_spans(&node, out),
+                SyntaxElement::Token(token) => out.push((token.kind(), token.span())),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_valid_programs() {
+        let src = "/// Doubles `x`.\nfn double(x: u32) -> u32 {\n    # no overflow check\n    return x * 2; /* yet */\n}\n\nlet table: [u8; 3] = [1, 2, 3,];\n";
+        for tree in trees(src) {
+            assert_eq!(tree.to_string(), src);
+        }
+    }
+
+    #[test]
+    fn round_trips_syntax_errors() {
+        let src = "let a = ;\nfn f() {\n    let b = 1 let c = 2;\n    if (c { }\n}\n/// dangling\n";
+        for tree in trees(src) {
+            assert_eq!(tree.to_string(), src);
+        }
+    }
+
+    #[test]
+    fn round_trips_text_the_lexer_rejected() {
+        let src = "let s = \"héllo\"; let x = 1 § 2;\n/* ünï */ let y = @ 3;\nlet q = \"unterminated\n";
+        for tree in trees(src) {
+            assert_eq!(tree.to_string(), src);
+        }
+    }
+
+    #[test]
+    fn token_spans_match_the_lexer() {
+        let src = "fn main() {\n    let s = \"ä\\n\"; /* two\n lines */ let t = s;\n}\n";
+        let lexed: Vec<(TokenType, Span)> = Lexer::with_trivia(src, 0)
+            .filter_map(Result::ok)
+            .filter(|token| token.ttype != TokenType::Eof)
+            .map(|token| (token.ttype, token.span))
+            .collect();
+
+        let mut spans = Vec::new();
+        token_spans(&trees(src)[1], &mut spans);
+        assert_eq!(spans, lexed);
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+
+use crate::span::{SourceMap, Span};
+
+// Codes are grouped by the stage that reports them:
+//   E00xx lexer, E01xx parser, E02xx program structure, E03xx name
+//   resolution, E04xx types, E05xx mutability, E06xx control flow, E07xx
+//   initialization, E08xx constant evaluation.
+// Warnings use the same numbering with a `W` prefix.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+// A fix: replace the text at `span` with `replacement`. An empty span
+// inserts.
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub span: Span,
+    pub message: String,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        return Self {
+            severity,
+            code,
+            message: message.into(),
+            primary: Label { span, message: String::new() },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        };
+    }
+
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        return Self::new(Severity::Error, code, message, span);
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        return Self::new(Severity::Warning, code, message, span);
+    }
+
+    // Text printed under the primary span.
+    pub fn with_label(mut self, message: impl Into<String>) -> Self {
+        self.primary.message = message.into();
+        return self;
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: message.into() });
+        return self;
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        return self;
+    }
+
+    pub fn with_suggestion(mut self, span: Span, message: impl Into<String>, replacement: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { span, message: message.into(), replacement: replacement.into() });
+        return self;
+    }
+
+    pub fn is_error(&self) -> bool {
+        return self.severity == Severity::Error;
+    }
+}
+
+// Renders `diag` the way rustc does: a header, the location, and every
+// labelled line of source with the labelled range underlined. The primary
+// label is drawn with `^`, secondary labels with `-`. Suggestions follow
+// as the fixed line, with inserted text marked `+` and replaced text `~`.
+pub fn render(diag: &Diagnostic, sources: &SourceMap) -> String {
+    let file = diag.primary.span.file;
+    let mut out = String::new();
+
+    out.push_str(&format!("{}[{}]: {}\n", diag.severity, diag.code, diag.message));
+
+    let mut labels: Vec<(&Label, bool)> = vec![(&diag.primary, true)];
+    for label in &diag.secondary {
+        if label.span.file == file {
+            labels.push((label, false));
+        }
+    }
+
+    let mut lines: Vec<usize> = labels.iter().map(|(label, _)| label.span.line).collect();
+    lines.sort();
+    lines.dedup();
+
+    let last_line = diag.suggestions.iter().map(|fix| fix.span.line).chain(lines.iter().copied()).max();
+    let gutter = last_line.unwrap_or(1).to_string().len();
+    let pad = " ".repeat(gutter);
+
+    out.push_str(&format!("{}--> {}:{}:{}\n", pad, sources.name(file), diag.primary.span.line, diag.primary.span.column));
+    out.push_str(&format!("{} |\n", pad));
+
+    let mut prev_line: Option<usize> = None;
+
+    for line in lines {
+        if let Some(prev) = prev_line {
+            if line > prev + 1 {
+                out.push_str("...\n");
+            }
+        }
+        prev_line = Some(line);
+
+        let text = sources.line(file, line);
+        out.push_str(&format!("{:>width$} | {}\n", line, text, width = gutter));
+
+        let mut on_line: Vec<&(&Label, bool)> = labels.iter().filter(|(label, _)| label.span.line == line).collect();
+        on_line.sort_by_key(|(label, _)| label.span.column);
+
+        for (label, primary) in on_line {
+            let marker = if *primary { "^" } else { "-" };
+
+            // Keep tabs in the indentation so the marker lines up with the text.
+            let indent: String = text
+                .chars()
+                .take(label.span.column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            let width = underline_width(sources.text(file), label.span);
+            let underline = marker.repeat(width);
+
+            if label.message.is_empty() {
+                out.push_str(&format!("{} | {}{}\n", pad, indent, underline));
+            } else {
+                out.push_str(&format!("{} | {}{} {}\n", pad, indent, underline, label.message));
+            }
+        }
+    }
+
+    if !diag.notes.is_empty() {
+        out.push_str(&format!("{} |\n", pad));
+        for note in &diag.notes {
+            out.push_str(&format!("{} = note: {}\n", pad, note));
+        }
+    }
+
+    for fix in diag.suggestions.iter().filter(|fix| fix.span.file == file) {
+        let text = sources.line(file, fix.span.line);
+
+        // Byte range of the span within its line.
+        let start: usize = text.chars().take(fix.span.column - 1).map(|c| c.len_utf8()).sum();
+        let end = (start + fix.span.end - fix.span.start).min(text.len());
+        let fixed = format!("{}{}{}", &text[..start], fix.replacement, &text[end..]);
+
+        let indent: String = text[..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let marker = if fix.span.start == fix.span.end { "+" } else { "~" };
+        let underline = marker.repeat(fix.replacement.trim_end().chars().count().max(1));
+
+        out.push_str(&format!("{} |\n", pad));
+        out.push_str(&format!("help: {}\n", fix.message));
+        out.push_str(&format!("{} |\n", pad));
+        out.push_str(&format!("{:>width$} | {}\n", fix.span.line, fixed, width = gutter));
+        out.push_str(&format!("{} | {}{}\n", pad, indent, underline));
+    }
+
+    return out;
+}
+
+// Number of characters to underline for `span`, stopping at the end of its
+// first line. Empty spans (end of file, missing tokens) still get one marker.
+fn underline_width(text: &str, span: Span) -> usize {
+    let end = span.end.min(text.len());
+    let start = span.start.min(end);
+
+    let width = text[start..end].chars().take_while(|c| *c != '\n').count();
+
+    return width.max(1);
+}
@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, Function, Item, Module, VarDecl};
+use crate::resolve::{DeclId, Resolution};
+use crate::span::Span;
+
+// How a program starts: an init routine evaluates the initializers of all
+// globals in source order, so every global needs one and none may read a
+// global that comes after it. Then the entry function (`main` unless
+// configured otherwise) is called. It takes no arguments and never returns a
+// value. Nothing else runs, so executable statements outside functions are
+// rejected.
+
+pub const DEFAULT_ENTRY: &str = "main";
+
+pub struct EntryPoint<'m> {
+    // Globals whose initializers the init routine runs, in order.
+    pub init: Vec<&'m VarDecl>,
+    pub entry: &'m Function,
+}
+
+// Checks `module` against the rules above. `file` is the module's file, used
+// to point at its start when the entry function is missing.
+pub fn check_entry<'m>(module: &'m Module, file: usize, entry: &str) -> (Option<EntryPoint<'m>>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+
+    for statement in &module.top_level_statements {
+        diagnostics.push(
+            Diagnostic::error("E0201", "statements are not allowed at the top level", statement.span())
+                .with_label("this is never executed")
+                .with_note(format!("only `fn`, `let`, `const` and `type` declarations may appear outside functions, move this into `fn {}`", entry)),
+        );
+    }
+
+    for item in &module.items {
+        if let Item::Global(VarDecl { name, name_span, value: None, .. }) = item {
+            diagnostics.push(
+                Diagnostic::error("E0204", format!("global `{}` has no initial value", name), *name_span)
+                    .with_label("declared without a value")
+                    .with_note("the init routine sets every global before the entry function runs, so each needs a value"),
+            );
+        }
+    }
+
+    let init = module.items.iter()
+        .filter_map(|item| match item {
+            Item::Global(var) => Some(var),
+            _ => None,
+        })
+        .collect();
+
+    let function = module.items.iter().find_map(|item| match item {
+        Item::Function(func) if func.name == entry => Some(func),
+        _ => None,
+    });
+
+    let function = match function {
+        Some(func) => func,
+        None => {
+            diagnostics.push(missing_entry(module, file, entry));
+            return (None, diagnostics);
+        }
+    };
+
+    if !function.arguments.is_empty() || function.ret_type.is_some() {
+        let mut diag = Diagnostic::error("E0203", format!("entry function `{}` has the wrong signature", entry), function.span)
+            .with_label(format!("expected `fn {}()`", entry));
+        if let Some(param) = function.arguments.first() {
+            diag = diag.with_secondary(param.span, "the entry function takes no parameters");
+        }
+        if let Some(ret_type) = &function.ret_type {
+            diag = diag.with_secondary(ret_type.span(), "the entry function returns nothing");
+        }
+        diagnostics.push(diag);
+    }
+
+    return (Some(EntryPoint { init, entry: function }), diagnostics);
+}
+
+fn missing_entry(module: &Module, file: usize, entry: &str) -> Diagnostic {
+    // Something else by that name is probably meant to be the entry.
+    let other = module.items.iter().find(|item| match item {
+        Item::Global(var) => var.name == entry,
+        Item::Const(constant) => constant.name == entry,
+        Item::Type(decl) => decl.name == entry,
+        Item::Function(_) | Item::Error(_) => false,
+    });
+
+    if let Some(item) = other {
+        return Diagnostic::error("E0202", format!("entry point `{}` is not a function", entry), item.span())
+            .with_label(format!("`{}` is declared here", entry))
+            .with_note(format!("execution starts by calling `fn {}()`", entry));
+    }
+
+    let start = Span { file, line: 1, column: 1, start: 0, end: 0 };
+    let diag = Diagnostic::error("E0202", format!("no `{}` function found", entry), start)
+        .with_note(format!("execution starts by calling `fn {}()`", entry));
+
+    if module.top_level_statements.is_empty() {
+        return diag;
+    }
+    return diag.with_note(format!("to run the top-level statements, wrap them in `fn {}() {{ ... }}`", entry));
+}
+
+// A global read, or a function called, by a piece of code.
+#[derive(Clone, Copy)]
+enum Use {
+    Read(DeclId, Span),
+    Call(DeclId, Span),
+}
+
+// The init routine runs the initializers in source order, so an initializer
+// may only read globals declared before it. Reads by the functions it calls,
+// directly or through further calls, count too. Runs after name resolution,
+// which tells a global from a local of the same name.
+pub fn check_global_order(module: &Module, resolution: &Resolution) -> Vec<Diagnostic> {
+    let mut globals = Vec::new();
+    let mut functions = Vec::new();
+    for item in &module.items {
+        match item {
+            Item::Global(var) => globals.push(var),
+            Item::Function(func) => collect_functions(func, &mut functions),
+            _ => {}
+        }
+    }
+
+    // Position of each global in the init routine.
+    let position: HashMap<DeclId, usize> = globals.iter()
+        .enumerate()
+        .filter_map(|(index, var)| Some((*resolution.declarations.get(&var.name_span)?, index)))
+        .collect();
+
+    let mut bodies = HashMap::new();
+    for func in &functions {
+        if let Some(id) = resolution.declarations.get(&func.name_span) {
+            let mut uses = Vec::new();
+            for statement in &func.block {
+                collect_uses(statement, resolution, &mut uses);
+            }
+            bodies.insert(*id, uses);
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+
+    for (index, var) in globals.iter().enumerate() {
+        let mut uses = Vec::new();
+        if let Some(value) = &var.value {
+            collect_uses(value, resolution, &mut uses);
+        }
+
+        let late = |id: &DeclId| position.get(id).is_some_and(|&at| at >= index);
+        let found = uses.iter().find_map(|used| match *used {
+            Use::Read(id, span) if late(&id) => Some((id, span, None)),
+            Use::Call(func, call) => late_read(func, &bodies, &late).map(|(id, span)| (id, span, Some(call))),
+            Use::Read(..) => None,
+        });
+
+        if let Some((id, span, call)) = found {
+            let read = globals[position[&id]];
+            let mut diag = match call {
+                None if read.name_span == var.name_span => {
+                    Diagnostic::error("E0205", format!("global `{}` is read before it is initialized", read.name), span)
+                        .with_label("read in its own initializer")
+                }
+                None => Diagnostic::error("E0205", format!("global `{}` is read before it is initialized", read.name), span)
+                    .with_label(format!("read while initializing `{}`", var.name)),
+                Some(call) => Diagnostic::error("E0205", format!("global `{}` is read before it is initialized", read.name), call)
+                    .with_label(format!("this call reads `{}` while initializing `{}`", read.name, var.name))
+                    .with_secondary(span, format!("`{}` is read here", read.name)),
+            };
+            if read.name_span != var.name_span {
+                diag = diag.with_secondary(read.name_span, format!("`{}` is initialized after `{}`", read.name, var.name));
+            }
+            diagnostics.push(diag.with_note("globals are initialized in source order, so an initializer can only read the globals declared before it"));
+        }
+    }
+
+    return diagnostics;
+}
+
+// The first read of a global `late` accepts that calling `func` leads to.
+fn late_read(func: DeclId, bodies: &HashMap<DeclId, Vec<Use>>, late: &dyn Fn(&DeclId) -> bool) -> Option<(DeclId, Span)> {
+    let mut visited = HashSet::from([func]);
+    let mut pending = vec![func];
+
+    while let Some(func) = pending.pop() {
+        for used in bodies.get(&func).into_iter().flatten() {
+            match *used {
+                Use::Read(id, span) if late(&id) => return Some((id, span)),
+                Use::Call(callee, _) if visited.insert(callee) => pending.push(callee),
+                _ => {}
+            }
+        }
+    }
+
+    return None;
+}
+
+// `func` and the functions declared inside it.
+fn collect_functions<'m>(func: &'m Function, out: &mut Vec<&'m Function>) {
+    out.push(func);
+    let mut blocks = vec![&func.block];
+
+    while let Some(block) = blocks.pop() {
+        for statement in block {
+            match statement {
+                ASTNode::FuncDef(inner) => collect_functions(inner, out),
+                ASTNode::IfElseNode { then_branch, elif_branch, else_branch, .. } => {
+                    blocks.push(then_branch);
+                    blocks.extend(elif_branch.iter().map(|(_, block)| block));
+                    blocks.extend(else_branch);
+                }
+                ASTNode::LoopNode { block, .. } => blocks.push(block),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Everything `node` reads or calls when it runs. Nested functions do not
+// run where they are declared and constants are evaluated at compile time,
+// so neither is looked into. Assigning to a variable or taking its address
+// does not read it.
+fn collect_uses(node: &ASTNode, resolution: &Resolution, out: &mut Vec<Use>) {
+    match node {
+        ASTNode::Identifier(_, span) => {
+            if let Some(id) = resolution.uses.get(span) {
+                out.push(Use::Read(*id, *span));
+            }
+        }
+
+        ASTNode::FuncCall { name_span, arguments, .. } => {
+            for argument in arguments {
+                collect_uses(argument, resolution, out);
+            }
+            if let Some(id) = resolution.uses.get(name_span) {
+                out.push(Use::Call(*id, *name_span));
+            }
+        }
+
+        ASTNode::UnaryOpNode { operand, .. } | ASTNode::DerefNode { operand, .. } => collect_uses(operand, resolution, out),
+        ASTNode::AddrOfNode { operand, .. } => collect_place_uses(operand, resolution, out),
+        ASTNode::CastNode { value, .. } => collect_uses(value, resolution, out),
+
+        ASTNode::BinOpNode { left, right, .. } => {
+            collect_uses(left, resolution, out);
+            collect_uses(right, resolution, out);
+        }
+
+        ASTNode::IndexNode { base, index, .. } => {
+            collect_uses(base, resolution, out);
+            collect_uses(index, resolution, out);
+        }
+
+        ASTNode::ArrayLiteral { elements, .. } => {
+            for element in elements {
+                collect_uses(element, resolution, out);
+            }
+        }
+
+        ASTNode::ArrayRepeat { value, count, .. } => {
+            collect_uses(value, resolution, out);
+            collect_uses(count, resolution, out);
+        }
+
+        ASTNode::VarDecNode(var) => {
+            if let Some(value) = &var.value {
+                collect_uses(value, resolution, out);
+            }
+        }
+
+        ASTNode::AssignNode { target, value, .. } => {
+            collect_place_uses(target, resolution, out);
+            collect_uses(value, resolution, out);
+        }
+
+        ASTNode::ReturnNode(Some(value), _) => collect_uses(value, resolution, out),
+
+        ASTNode::IfElseNode { condition, then_branch, elif_branch, else_branch, .. } => {
+            collect_uses(condition, resolution, out);
+            for statement in then_branch {
+                collect_uses(statement, resolution, out);
+            }
+            for (condition, block) in elif_branch {
+                collect_uses(condition, resolution, out);
+                for statement in block {
+                    collect_uses(statement, resolution, out);
+                }
+            }
+            for statement in else_branch.iter().flatten() {
+                collect_uses(statement, resolution, out);
+            }
+        }
+
+        ASTNode::LoopNode { condition, block, .. } => {
+            collect_uses(condition, resolution, out);
+            for statement in block {
+                collect_uses(statement, resolution, out);
+            }
+        }
+
+        _ => {}
+    }
+}
+
+// Uses in a place that is written or borrowed: the variable itself is not
+// read, but pointers and indices on the way to it are.
+fn collect_place_uses(node: &ASTNode, resolution: &Resolution, out: &mut Vec<Use>) {
+    match node {
+        ASTNode::Identifier(..) => {}
+        ASTNode::IndexNode { base, index, .. } => {
+            collect_place_uses(base, resolution, out);
+            collect_uses(index, resolution, out);
+        }
+        _ => collect_uses(node, resolution, out),
+    }
+}
@@ -0,0 +1,202 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, Function, Item, Module};
+use crate::span::Span;
+use crate::typeck::Typing;
+
+// Checks how control leaves statements. A `break` needs a loop around it in
+// the same function, a function with a return type must return a value on
+// every path, and a function returns either always or never a value.
+// Statements that can never run are warned about.
+//
+// A statement diverges when control never reaches the statement after it:
+// `return`, `break`, an `if` with an `else` whose branches all diverge, and
+// a `loop (true)` without a `break`.
+
+struct Checker<'m> {
+    typing: &'m Typing,
+    // One entry per loop around the statement being checked, set once the
+    // loop has a `break`.
+    loops: Vec<bool>,
+    // `return;` and `return value;` statements of the current function.
+    returns: Vec<(Span, Option<Span>)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check(module: &Module, typing: &Typing) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        typing,
+        loops: Vec::new(),
+        returns: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for item in &module.items {
+        if let Item::Function(func) = item {
+            checker.check_function(func);
+        }
+    }
+
+    return checker.diagnostics;
+}
+
+impl<'m> Checker<'m> {
+
+    fn check_function(&mut self, func: &Function) {
+        let loops = std::mem::take(&mut self.loops);
+        let returns = std::mem::take(&mut self.returns);
+
+        let diverges = self.check_block(&func.block);
+
+        if let (Some(ret_type), None) = (&func.ret_type, diverges) {
+            let mut diag = Diagnostic::error("E0602", format!("function `{}` can end without returning a value", func.name), func.name_span)
+                .with_label(format!("not every path through `{}` returns", func.name))
+                .with_secondary(ret_type.span(), format!("`{}` must return a `{}`", func.name, ret_type));
+            if let Some(last) = func.block.last() {
+                diag = diag.with_secondary(last.span(), "execution can continue past this");
+            }
+            self.diagnostics.push(diag.with_note(format!("add a `return` at the end of `{}`", func.name)));
+        }
+
+        self.check_returns(func);
+
+        self.loops = loops;
+        self.returns = returns;
+    }
+
+    // Whether each `return` has a value must agree with the function's
+    // return type, and with the other returns.
+    fn check_returns(&mut self, func: &Function) {
+        let bare = self.returns.iter().find(|(_, value)| value.is_none()).map(|(span, _)| *span);
+        let with_value = self.returns.iter().find_map(|(span, value)| value.map(|value| (*span, value)));
+
+        match (bare, with_value, &func.ret_type) {
+            (Some(bare), Some((with_value, _)), _) => {
+                let (first, second) = if bare.start < with_value.start { (bare, with_value) } else { (with_value, bare) };
+                let note = match &func.ret_type {
+                    Some(ret_type) => format!("`{}` returns a `{}`, so every `return` needs a value", func.name, ret_type),
+                    None => format!("`{}` has no return type, so no `return` may have a value", func.name),
+                };
+                self.diagnostics.push(
+                    Diagnostic::error("E0603", format!("function `{}` returns both with and without a value", func.name), second)
+                        .with_label(if second == bare { "returns without a value" } else { "returns a value" })
+                        .with_secondary(first, if first == bare { "returns without a value" } else { "returns a value" })
+                        .with_note(note),
+                );
+            }
+
+            (Some(bare), None, Some(ret_type)) => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0604", format!("`return;` in a function that returns `{}`", ret_type), bare)
+                        .with_label(format!("expected a `{}` to return", ret_type))
+                        .with_secondary(ret_type.span(), "return type declared here"),
+                );
+            }
+
+            (None, Some((_, value)), None) => {
+                let diag = Diagnostic::error("E0605", format!("function `{}` has no return type but returns a value", func.name), value)
+                    .with_label("this value is returned")
+                    .with_secondary(func.name_span, format!("`{}` has no return type", func.name));
+                let diag = match self.typing.exprs.get(&value) {
+                    Some(ty) if !ty.is_error() => diag.with_note(format!("to return `{}`, declare it: `-> {}`", ty, ty)),
+                    _ => diag,
+                };
+                self.diagnostics.push(diag);
+            }
+
+            _ => {}
+        }
+    }
+
+    // Returns the statement the block diverges at, if it does. The
+    // statements after it are reported.
+    fn check_block(&mut self, block: &[ASTNode]) -> Option<Span> {
+        let mut diverges = None;
+
+        for (i, statement) in block.iter().enumerate() {
+            if let Some(exit) = self.check_statement(statement) {
+                diverges = Some(exit);
+                self.unreachable(&block[i + 1..], statement.span());
+                break;
+            }
+        }
+
+        return diverges;
+    }
+
+    // Warns about the statements in `rest` that would run after `exit`.
+    // Nested declarations are never run anyway.
+    fn unreachable(&mut self, rest: &[ASTNode], exit: Span) {
+        let is_decl = |node: &ASTNode| matches!(node, ASTNode::FuncDef(_) | ASTNode::TypeDecl(_) | ASTNode::ConstDecl(_));
+
+        let first = rest.iter().find(|node| !is_decl(node));
+        let last = rest.iter().rev().find(|node| !is_decl(node));
+
+        if let (Some(first), Some(last)) = (first, last) {
+            self.diagnostics.push(
+                Diagnostic::warning("W0601", "unreachable statement", first.span().to(last.span()))
+                    .with_label("this is never executed")
+                    .with_secondary(exit, "any code following this statement is unreachable"),
+            );
+        }
+
+        // Their own problems are still reported.
+        for node in rest {
+            self.check_statement(node);
+        }
+    }
+
+    // Returns the span of `node` if it diverges.
+    fn check_statement(&mut self, node: &ASTNode) -> Option<Span> {
+        match node {
+            ASTNode::ReturnNode(value, span) => {
+                self.returns.push((*span, value.as_ref().map(|value| value.span())));
+                return Some(*span);
+            }
+
+            ASTNode::BreakNode(span) => {
+                match self.loops.last_mut() {
+                    Some(breaks) => {
+                        *breaks = true;
+                        return Some(*span);
+                    }
+                    None => {
+                        self.diagnostics.push(
+                            Diagnostic::error("E0601", "`break` outside of a loop", *span)
+                                .with_label("cannot `break` here")
+                                .with_note("`break` only leaves a `loop` in the same function"),
+                        );
+                        return None;
+                    }
+                }
+            }
+
+            ASTNode::IfElseNode { then_branch, elif_branch, else_branch, span, .. } => {
+                let mut all = self.check_block(then_branch).is_some();
+                for (_, block) in elif_branch {
+                    all &= self.check_block(block).is_some();
+                }
+                all &= match else_branch {
+                    Some(block) => self.check_block(block).is_some(),
+                    None => false,
+                };
+                return if all { Some(*span) } else { None };
+            }
+
+            ASTNode::LoopNode { condition, block, span } => {
+                self.loops.push(false);
+                self.check_block(block);
+                let breaks = self.loops.pop().unwrap();
+
+                let forever = matches!(condition.as_ref(), ASTNode::BoolNode(true, _));
+                return if forever && !breaks { Some(*span) } else { None };
+            }
+
+            ASTNode::FuncDef(func) => {
+                self.check_function(func);
+                return None;
+            }
+
+            _ => return None,
+        }
+    }
+}
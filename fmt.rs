@@ -0,0 +1,494 @@
+use crate::cst::{NodeKind, SyntaxElement, SyntaxNode, SyntaxToken};
+use crate::lexer::TokenType;
+
+// Canonical layout for Zerl source, printed from the lossless syntax tree so
+// comments survive. The tree is first turned into a layout document, then
+// groups that do not fit in MAX_WIDTH are broken over several lines.
+
+const MAX_WIDTH: usize = 100;
+const INDENT: usize = 4;
+
+enum Doc {
+    Text(String),
+    // A space, or a line break if the enclosing group is broken.
+    Line,
+    // Nothing, or a line break if the enclosing group is broken.
+    SoftLine,
+    // Always a line break. Groups containing one are always broken.
+    HardLine,
+    // Printed only if the enclosing group is broken.
+    IfBreak(&'static str),
+    Indent(Vec<Doc>),
+    Group(Vec<Doc>),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+struct Comment {
+    text: String,
+    own_line: bool,
+    blank_before: bool,
+}
+
+// A significant child of a node together with the comments before it.
+struct Part {
+    leading: Vec<Comment>,
+    blank_before: bool,
+    element: SyntaxElement,
+}
+
+pub fn format(tree: &SyntaxNode) -> String {
+    return render(&format_node(tree));
+}
+
+fn format_node(node: &SyntaxNode) -> Vec<Doc> {
+    let (parts, trailing) = parts(node);
+
+    match node.kind() {
+        NodeKind::Root => {
+            let mut docs = statements(&parts, &trailing);
+            if docs.is_empty() {
+                return docs;
+            }
+            docs.remove(0); // no line break before the first statement
+            docs.push(Doc::HardLine);
+            return docs;
+        }
+
+        NodeKind::Block => format_block(&parts),
+
+        NodeKind::ParamList | NodeKind::ArgList | NodeKind::ArrayLiteral => format_list(&parts),
+
+        NodeKind::BinOp => {
+            let mut docs = inline(&parts[..2]);
+            let mut right = vec![Doc::Line];
+            right.extend(inline(&parts[2..]));
+            docs.push(Doc::Indent(right));
+            return vec![Doc::Group(docs)];
+        }
+
+        _ => inline(&parts),
+    }
+}
+
+fn format_element(element: &SyntaxElement) -> Vec<Doc> {
+    match element {
+        SyntaxElement::Node(node) => format_node(node),
+        SyntaxElement::Token(token) if token.kind() == TokenType::Doc => {
+            vec![Doc::Text(token.text().trim_end().to_string())]
+        }
+        SyntaxElement::Token(token) => vec![Doc::Text(token.text().to_string())],
+    }
+}
+
+// Splits the children of `node` into significant parts, each carrying the
+// comments in front of it. Comments after the last part are returned
+// separately.
+fn parts(node: &SyntaxNode) -> (Vec<Part>, Vec<Comment>) {
+    let mut parts = Vec::new();
+    let mut comments = Vec::new();
+
+    // The start of the file counts as a line start.
+    let mut newlines = if node.kind() == NodeKind::Root { 1 } else { 0 };
+
+    for child in node.children() {
+        match &child {
+            SyntaxElement::Token(token) if token.kind() == TokenType::Whitespace => {
+                newlines += token.text().matches('\n').count();
+            }
+            SyntaxElement::Token(token) if token.kind() == TokenType::Comment => {
+                comments.push(Comment {
+                    text: token.text().trim_end().to_string(),
+                    own_line: newlines > 0,
+                    blank_before: newlines > 1,
+                });
+                newlines = 0;
+            }
+            _ => {
+                parts.push(Part {
+                    leading: std::mem::take(&mut comments),
+                    blank_before: newlines > 1,
+                    element: child,
+                });
+                newlines = 0;
+            }
+        }
+    }
+
+    return (parts, comments);
+}
+
+// One statement per line, keeping at most one blank line between them.
+// Comments that had a line of their own keep it, the others stay at the end
+// of the line before. Every statement starts with a line break.
+fn statements(parts: &[Part], trailing: &[Comment]) -> Vec<Doc> {
+    let mut docs = Vec::new();
+
+    for part in parts {
+        for comment in &part.leading {
+            push_comment(&mut docs, comment);
+        }
+        if part.blank_before && !docs.is_empty() {
+            docs.push(Doc::HardLine);
+        }
+        docs.push(Doc::HardLine);
+        docs.extend(format_element(&part.element));
+    }
+
+    for comment in trailing {
+        push_comment(&mut docs, comment);
+    }
+
+    return docs;
+}
+
+fn push_comment(docs: &mut Vec<Doc>, comment: &Comment) {
+    if comment.own_line || docs.is_empty() {
+        if comment.blank_before && !docs.is_empty() {
+            docs.push(Doc::HardLine);
+        }
+        docs.push(Doc::HardLine);
+    } else {
+        docs.push(Doc::Text(" ".to_string()));
+    }
+    docs.push(Doc::Text(comment.text.clone()));
+}
+
+fn format_block(parts: &[Part]) -> Vec<Doc> {
+    let (open, rest) = parts.split_first().unwrap();
+    let (close, body) = rest.split_last().unwrap();
+
+    if body.is_empty() && close.leading.is_empty() {
+        return vec![Doc::Text("{}".to_string())];
+    }
+
+    let mut docs = format_element(&open.element);
+    docs.push(Doc::Indent(statements(body, &close.leading)));
+    docs.push(Doc::HardLine);
+    docs.extend(format_element(&close.element));
+    return docs;
+}
+
+// An element of a list with the comments around it. `leading` comments
+// have lines of their own, `trailing` ones follow the element's comma.
+struct Element<'a> {
+    leading: Vec<&'a Comment>,
+    docs: Vec<Doc>,
+    trailing: Vec<&'a Comment>,
+}
+
+// `(a, b)`, `[a, b]`: all on one line if it fits, otherwise one element per
+// line with a trailing comma. A comment at the end of an element's line stays
+// there, after the comma.
+fn format_list(parts: &[Part]) -> Vec<Doc> {
+    let (open, rest) = parts.split_first().unwrap();
+    let (close, items) = rest.split_last().unwrap();
+
+    // Comments on the line of the opening delimiter, and on lines of their
+    // own after the last element.
+    let mut after_open: Vec<&Comment> = Vec::new();
+    let mut after_last: Vec<&Comment> = Vec::new();
+    let mut elements: Vec<Element> = Vec::new();
+    let mut current: Option<Element> = None;
+
+    for part in items {
+        let is_comma = matches!(&part.element, SyntaxElement::Token(token) if token.kind() == TokenType::Com);
+        if is_comma {
+            // Between an element and its comma.
+            if let Some(mut element) = current.take() {
+                element.trailing.extend(&part.leading);
+                elements.push(element);
+            }
+            continue;
+        }
+
+        let mut element = Element { leading: Vec::new(), docs: format_element(&part.element), trailing: Vec::new() };
+        for comment in &part.leading {
+            if comment.own_line {
+                element.leading.push(comment);
+            } else {
+                match elements.last_mut() {
+                    Some(previous) => previous.trailing.push(comment),
+                    None => after_open.push(comment),
+                }
+            }
+        }
+        current = Some(element);
+    }
+
+    for comment in &close.leading {
+        if comment.own_line {
+            after_last.push(comment);
+        } else {
+            match (current.as_mut(), elements.last_mut()) {
+                (Some(element), _) | (None, Some(element)) => element.trailing.push(comment),
+                (None, None) => after_open.push(comment),
+            }
+        }
+    }
+    elements.extend(current);
+
+    let mut docs = format_element(&open.element);
+    if elements.is_empty() && after_open.is_empty() && after_last.is_empty() {
+        docs.extend(format_element(&close.element));
+        return docs;
+    }
+
+    // Whether the line has to end, after a `#` comment.
+    let mut hard = push_trailing(&mut docs, &after_open);
+    let mut body = Vec::new();
+    let count = elements.len();
+
+    for (i, element) in elements.into_iter().enumerate() {
+        body.push(if hard { Doc::HardLine } else if i == 0 { Doc::SoftLine } else { Doc::Line });
+        for comment in element.leading {
+            body.push(Doc::Text(comment.text.clone()));
+            body.push(Doc::HardLine);
+        }
+        body.extend(element.docs);
+        if i + 1 < count {
+            body.push(Doc::Text(",".to_string()));
+        } else {
+            body.push(Doc::IfBreak(","));
+        }
+        hard = push_trailing(&mut body, &element.trailing);
+    }
+
+    for comment in &after_last {
+        body.push(Doc::HardLine);
+        body.push(Doc::Text(comment.text.clone()));
+        hard = true;
+    }
+
+    docs.push(Doc::Indent(body));
+    docs.push(if hard { Doc::HardLine } else { Doc::SoftLine });
+    docs.extend(format_element(&close.element));
+    return vec![Doc::Group(docs)];
+}
+
+// Appends comments that continue the current line. Returns whether one of
+// them runs to the end of the line.
+fn push_trailing(docs: &mut Vec<Doc>, comments: &[&Comment]) -> bool {
+    for comment in comments {
+        docs.push(Doc::Text(" ".to_string()));
+        docs.push(Doc::Text(comment.text.clone()));
+    }
+    return comments.iter().any(|comment| comment.text.starts_with('#'));
+}
+
+// Parts on one line, separated according to `space_between`. A doc comment
+// or line comment ends the line.
+fn inline(parts: &[Part]) -> Vec<Doc> {
+    let mut docs = Vec::new();
+    let mut prev: Option<SyntaxToken> = None;
+
+    for part in parts {
+        let first = first_token(&part.element);
+
+        let mut space = match (&prev, &first) {
+            (Some(prev), Some(first)) => space_between(prev, first),
+            _ => false,
+        };
+
+        if prev.as_ref().is_some_and(|prev| prev.kind() == TokenType::Doc) {
+            docs.push(Doc::HardLine);
+            space = false;
+        }
+
+        for comment in &part.leading {
+            space = push_inline_comment(&mut docs, comment, prev.is_some());
+        }
+
+        if space {
+            docs.push(Doc::Text(" ".to_string()));
+        }
+        docs.extend(format_element(&part.element));
+        prev = last_token(&part.element).or(prev);
+    }
+
+    return docs;
+}
+
+// Returns whether code may follow on the same line.
+fn push_inline_comment(docs: &mut Vec<Doc>, comment: &Comment, space_before: bool) -> bool {
+    if space_before {
+        docs.push(Doc::Text(" ".to_string()));
+    }
+    docs.push(Doc::Text(comment.text.clone()));
+    if comment.text.starts_with('#') {
+        docs.push(Doc::HardLine);
+        return false;
+    }
+    return true;
+}
+
+// Whether a space goes between two adjacent tokens. Operators that are both
+// prefix and binary (`-`, `*`, `&`) are told apart by their parent node.
+fn space_between(prev: &SyntaxToken, next: &SyntaxToken) -> bool {
+    match next.kind() {
+        TokenType::Scln | TokenType::Com | TokenType::Cln | TokenType::Csq | TokenType::Cpt => return false,
+        TokenType::Osq if next.parent().kind() == NodeKind::Index => return false,
+        TokenType::Opt if matches!(next.parent().kind(), NodeKind::ArgList | NodeKind::ParamList) => return false,
+        _ => {}
+    }
+
+    match prev.kind() {
+        TokenType::Opt | TokenType::Osq => false,
+        TokenType::Sub | TokenType::Not | TokenType::Tilde | TokenType::Mul | TokenType::Amp => {
+            !matches!(prev.parent().kind(), NodeKind::UnaryOp | NodeKind::Deref | NodeKind::AddrOf | NodeKind::Type)
+        }
+        _ => true,
+    }
+}
+
+fn first_token(element: &SyntaxElement) -> Option<SyntaxToken> {
+    match element {
+        SyntaxElement::Token(token) if token.kind().is_trivia() => None,
+        SyntaxElement::Token(token) => Some(token.clone()),
+        SyntaxElement::Node(node) => node.children().iter().find_map(first_token),
+    }
+}
+
+fn last_token(element: &SyntaxElement) -> Option<SyntaxToken> {
+    match element {
+        SyntaxElement::Token(token) if token.kind().is_trivia() => None,
+        SyntaxElement::Token(token) => Some(token.clone()),
+        SyntaxElement::Node(node) => node.children().iter().rev().find_map(last_token),
+    }
+}
+
+fn render(docs: &[Doc]) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack: Vec<(usize, Mode, &Doc)> = docs.iter().rev().map(|doc| (0, Mode::Break, doc)).collect();
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => {
+                out.push_str(text);
+                column = match text.rfind('\n') {
+                    Some(i) => text[i + 1..].chars().count(),
+                    None => column + text.chars().count(),
+                };
+            }
+
+            Doc::Line if mode == Mode::Flat => {
+                out.push(' ');
+                column += 1;
+            }
+
+            Doc::SoftLine if mode == Mode::Flat => {}
+
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                while out.ends_with(' ') {
+                    out.pop();
+                }
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+
+            Doc::IfBreak(text) => {
+                if mode == Mode::Break {
+                    out.push_str(text);
+                    column += text.len();
+                }
+            }
+
+            Doc::Indent(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (indent + INDENT, mode, doc)));
+            }
+
+            Doc::Group(docs) => {
+                let flat = mode == Mode::Flat || fits(MAX_WIDTH as isize - column as isize, docs, &stack);
+                let mode = if flat { Mode::Flat } else { Mode::Break };
+                stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+            }
+        }
+    }
+
+    // Blank lines at the end were indented before it was known they end the
+    // output.
+    while out.ends_with(' ') {
+        out.pop();
+    }
+
+    return out;
+}
+
+// Whether `docs` printed flat, followed by what comes after them up to the
+// next line break, fit in `width` columns.
+fn fits(mut width: isize, docs: &[Doc], rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut pending: Vec<(Mode, &Doc)> = docs.iter().rev().map(|doc| (Mode::Flat, doc)).collect();
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let (mode, doc) = match pending.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some((_, mode, doc)) => (*mode, *doc),
+                None => return true,
+            },
+        };
+
+        match doc {
+            Doc::Text(text) => {
+                if text.contains('\n') {
+                    return mode == Mode::Break;
+                }
+                width -= text.chars().count() as isize;
+            }
+            Doc::Line if mode == Mode::Flat => width -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::HardLine if mode == Mode::Flat => return false,
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::IfBreak(text) => {
+                if mode == Mode::Break {
+                    width -= text.len() as isize;
+                }
+            }
+            Doc::Indent(docs) | Doc::Group(docs) => {
+                pending.extend(docs.iter().rev().map(|doc| (mode, doc)));
+            }
+        }
+
+        if width < 0 {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format;
+
+    fn format_source(src: &str) -> String {
+        let tree = crate::syntax_tree(src, 0).unwrap_or_else(|_| panic!("does not parse:\n{}", src));
+        return format(&tree);
+    }
+
+    #[test]
+    fn reprints_in_the_canonical_layout() {
+        let src = "fn main( ) -> u32 {let x:u32=1+2 ; # sum\nreturn x;}\n";
+        assert_eq!(format_source(src), "fn main() -> u32 {\n    let x: u32 = 1 + 2; # sum\n    return x;\n}\n");
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let sources = [
+            "/// doc one\n///  doc two\nconst A: u8 = (1 + 2) * 3;\n",
+            "fn main() {\n    let mut x: [u8; 3] = [1, 2, 3,];\n    let p: *mut u8 = &mut x[0];\n    *p = -(2u8 as u8) as u8;\n    if (x[0] == 5) { x[2] = 1; } elif (true) { } else { loop (false) { break; } }\n}\n",
+            "fn long(first_argument: u32, second_argument: u32, third_argument: u32, fourth: u32) -> u32 {\n    return first_argument + second_argument + third_argument + fourth + first_argument * 2;\n}\n",
+            "let table: [u8; 4] = [\n    1, # one\n    2,\n    /* three */ 3, 4,\n];\n\n\n\n# trailing\n",
+        ];
+
+        for src in sources {
+            let once = format_source(src);
+            assert_eq!(format_source(&once), once, "formatting again changed the output of:\n{}", src);
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, Function, Item, Module};
+use crate::resolve::{DeclId, Resolution};
+use crate::span::Span;
+use crate::typeck::Typing;
+use crate::types::Type;
+
+// Checks that a local declared without a value (`let x: u32;`) is assigned
+// on every path before it is read, and that an immutable one is assigned
+// only once. Elements are not tracked one by one: writing any element of an
+// array counts as assigning the array, and so does taking a `*mut` pointer
+// to it, since whoever gets the pointer is expected to fill it in.
+
+// Why a variable may be unassigned: a point on some path that gets here,
+// and what happens there.
+#[derive(Debug, Clone, PartialEq)]
+struct Why {
+    span: Span,
+    message: String,
+}
+
+// What is known about the tracked locals at one point of a function.
+#[derive(Debug, Clone, Default)]
+struct State {
+    // Locals that are not assigned on some path to this point.
+    unassigned: HashMap<DeclId, Why>,
+    // Immutable locals that are assigned on some path, and where.
+    assigned: HashMap<DeclId, Span>,
+}
+
+// `None` where control cannot reach.
+type Flow = Option<State>;
+
+// A way into a join point, and what to say about a variable that is
+// unassigned only along it.
+struct Path {
+    flow: Flow,
+    span: Span,
+    message: &'static str,
+}
+
+struct Checker<'m> {
+    resolution: &'m Resolution,
+    typing: &'m Typing,
+    // Locals declared without a value, the only ones tracked.
+    deferred: HashSet<DeclId>,
+    // Ways out of each loop around the current statement, through `break`.
+    loops: Vec<Vec<Path>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check(module: &Module, resolution: &Resolution, typing: &Typing) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        resolution,
+        typing,
+        deferred: HashSet::new(),
+        loops: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for item in &module.items {
+        if let Item::Function(func) = item {
+            checker.check_function(func);
+        }
+    }
+
+    return checker.diagnostics;
+}
+
+impl<'m> Checker<'m> {
+
+    fn check_function(&mut self, func: &Function) {
+        let loops = std::mem::take(&mut self.loops);
+        let mut flow = Some(State::default());
+        self.check_block(&func.block, &mut flow);
+        self.loops = loops;
+    }
+
+    fn check_block(&mut self, block: &[ASTNode], flow: &mut Flow) {
+        for statement in block {
+            self.check_statement(statement, flow);
+        }
+    }
+
+    fn check_statement(&mut self, node: &ASTNode, flow: &mut Flow) {
+        match node {
+            ASTNode::VarDecNode(var) => match &var.value {
+                Some(value) => self.read(value, flow),
+                None => {
+                    let id = self.resolution.declarations.get(&var.name_span).copied();
+                    if let Some(id) = id {
+                        self.deferred.insert(id);
+                    }
+                    if let (Some(id), Some(state)) = (id, flow.as_mut()) {
+                        // A loop may come back to the declaration with the
+                        // variable assigned by the previous iteration.
+                        state.assigned.remove(&id);
+                        state.unassigned.insert(id, Why {
+                            span: var.name_span,
+                            message: format!("`{}` is declared here without a value", var.name),
+                        });
+                    }
+                }
+            },
+
+            ASTNode::ConstDecl(constant) => self.read(&constant.value, flow),
+
+            ASTNode::FuncDef(func) => self.check_function(func),
+
+            ASTNode::IfElseNode { condition, then_branch, elif_branch, else_branch, .. } => {
+                self.read(condition, flow);
+                let mut paths = Vec::new();
+
+                let mut then = flow.clone();
+                self.check_block(then_branch, &mut then);
+                paths.push(Path { flow: then, span: condition.span(), message: "if this condition is true" });
+
+                let mut last = condition.span();
+                for (condition, block) in elif_branch {
+                    self.read(condition, flow);
+                    let mut branch = flow.clone();
+                    self.check_block(block, &mut branch);
+                    paths.push(Path { flow: branch, span: condition.span(), message: "if this condition is true" });
+                    last = condition.span();
+                }
+
+                let mut otherwise = flow.clone();
+                if let Some(block) = else_branch {
+                    self.check_block(block, &mut otherwise);
+                }
+                paths.push(Path { flow: otherwise, span: last, message: "if this condition is false" });
+
+                *flow = self.join(flow, paths);
+            }
+
+            ASTNode::LoopNode { condition, block, span } => self.check_loop(condition, block, *span, flow),
+
+            ASTNode::ReturnNode(value, _) => {
+                if let Some(value) = value {
+                    self.read(value, flow);
+                }
+                *flow = None;
+            }
+
+            ASTNode::BreakNode(span) => {
+                if let Some(exits) = self.loops.last_mut() {
+                    exits.push(Path { flow: flow.take(), span: *span, message: "when the loop is left here" });
+                }
+            }
+
+            ASTNode::AssignNode { target, value, .. } => {
+                self.read(value, flow);
+                self.write(target, flow);
+            }
+
+            _ => self.read(node, flow),
+        }
+    }
+
+    // The body runs with what is known before the loop. It is checked a
+    // second time as a later iteration, which also sees what the first
+    // assigned.
+    fn check_loop(&mut self, condition: &ASTNode, block: &[ASTNode], span: Span, flow: &mut Flow) {
+        self.read(condition, flow);
+
+        let reported = self.diagnostics.len();
+        self.loops.push(Vec::new());
+        let mut first = flow.clone();
+        self.check_block(block, &mut first);
+        self.loops.pop();
+        self.diagnostics.truncate(reported);
+
+        let head = self.join(flow, vec![
+            Path { flow: flow.clone(), span: condition.span(), message: "if the loop does not run" },
+            Path { flow: first, span, message: "after the loop runs" },
+        ]);
+
+        self.loops.push(Vec::new());
+        let mut body = head.clone();
+        self.check_block(block, &mut body);
+        let mut exits = self.loops.pop().unwrap();
+
+        // `loop (true)` is only left through `break`.
+        if !matches!(condition, ASTNode::BoolNode(true, _)) {
+            exits.push(Path { flow: head, span: condition.span(), message: "if the loop does not run" });
+        }
+
+        *flow = self.join(flow, exits);
+    }
+
+    // Merges the paths into a join point that `before` dominates.
+    fn join(&self, before: &Flow, paths: Vec<Path>) -> Flow {
+        let reachable: Vec<&Path> = paths.iter().filter(|path| path.flow.is_some()).collect();
+        if reachable.is_empty() {
+            return None;
+        }
+
+        let mut state = State::default();
+        for path in &reachable {
+            let path_state = path.flow.as_ref().unwrap();
+
+            for (id, why) in &path_state.unassigned {
+                if state.unassigned.contains_key(id) {
+                    continue;
+                }
+                let everywhere = reachable.iter().all(|other| other.flow.as_ref().unwrap().unassigned.contains_key(id));
+                let inherited = before.as_ref().and_then(|before| before.unassigned.get(id)) == Some(why);
+
+                // Say how this path misses the assignment, unless the path
+                // already says so itself.
+                let why = if everywhere || !inherited {
+                    why.clone()
+                } else {
+                    Why {
+                        span: path.span,
+                        message: format!("{}, `{}` is not assigned", path.message, self.resolution.decl(*id).name),
+                    }
+                };
+                state.unassigned.insert(*id, why);
+            }
+
+            for (id, span) in &path_state.assigned {
+                state.assigned.entry(*id).or_insert(*span);
+            }
+        }
+
+        return Some(state);
+    }
+
+    // Records an assignment to `target`.
+    fn write(&mut self, target: &ASTNode, flow: &mut Flow) {
+        match target {
+            ASTNode::Identifier(_, span) => self.assign(*span, true, flow),
+
+            ASTNode::IndexNode { base, index, .. } => {
+                self.read(index, flow);
+                match self.typing.exprs.get(&base.span()) {
+                    Some(Type::Array { .. }) => self.write_element(base, flow),
+                    _ => self.read(base, flow),
+                }
+            }
+
+            _ => self.read(target, flow),
+        }
+    }
+
+    // Records a write to part of `place`, which counts as assigning the
+    // variable it belongs to.
+    fn write_element(&mut self, place: &ASTNode, flow: &mut Flow) {
+        match place {
+            ASTNode::Identifier(_, span) => self.assign(*span, false, flow),
+            _ => self.write(place, flow),
+        }
+    }
+
+    // `whole` is false when only part of the variable named at `span` is
+    // written, which neither needs nor gives up the right to assign it.
+    fn assign(&mut self, span: Span, whole: bool, flow: &mut Flow) {
+        let (id, state) = match (self.resolution.uses.get(&span), flow.as_mut()) {
+            (Some(&id), Some(state)) if self.deferred.contains(&id) => (id, state),
+            _ => return,
+        };
+        let decl = self.resolution.decl(id);
+
+        if whole && !decl.mutable {
+            if let Some(&previous) = state.assigned.get(&id) {
+                let diag = Diagnostic::error("E0702", format!("cannot assign twice to immutable local variable `{}`", decl.name), span)
+                    .with_label("cannot assign twice");
+                let diag = if previous == span {
+                    diag.with_note("the loop around this assignment may run it again")
+                } else {
+                    diag.with_secondary(previous, "it may already be assigned here")
+                };
+                self.diagnostics.push(
+                    diag.with_suggestion(Span { end: decl.span.start, ..decl.span }, format!("consider making `{}` mutable", decl.name), "mut "),
+                );
+            }
+            state.assigned.entry(id).or_insert(span);
+        }
+
+        state.unassigned.remove(&id);
+    }
+
+    // Checks every variable `node` reads.
+    fn read(&mut self, node: &ASTNode, flow: &mut Flow) {
+        match node {
+            ASTNode::Identifier(_, span) => self.use_of(*span, flow),
+
+            // Whoever gets a `*mut` pointer may fill the variable in.
+            ASTNode::AddrOfNode { mutable: true, operand, .. } => self.write_element(operand, flow),
+
+            ASTNode::FuncCall { arguments, .. } => {
+                for argument in arguments {
+                    self.read(argument, flow);
+                }
+            }
+
+            ASTNode::UnaryOpNode { operand, .. }
+            | ASTNode::DerefNode { operand, .. }
+            | ASTNode::AddrOfNode { operand, .. } => self.read(operand, flow),
+
+            ASTNode::CastNode { value, .. } => self.read(value, flow),
+
+            ASTNode::BinOpNode { left, right, .. } => {
+                self.read(left, flow);
+                self.read(right, flow);
+            }
+
+            ASTNode::IndexNode { base, index, .. } => {
+                self.read(base, flow);
+                self.read(index, flow);
+            }
+
+            ASTNode::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.read(element, flow);
+                }
+            }
+
+            ASTNode::ArrayRepeat { value, .. } => self.read(value, flow),
+
+            _ => {}
+        }
+    }
+
+    fn use_of(&mut self, span: Span, flow: &mut Flow) {
+        let (id, state) = match (self.resolution.uses.get(&span), flow.as_mut()) {
+            (Some(&id), Some(state)) => (id, state),
+            _ => return,
+        };
+
+        // Reported once, the reads after this one would say the same.
+        let why = match state.unassigned.remove(&id) {
+            Some(why) => why,
+            None => return,
+        };
+
+        let decl = self.resolution.decl(id);
+        let diag = if why.span == decl.span {
+            Diagnostic::error("E0701", format!("`{}` is used before it is assigned", decl.name), span)
+                .with_label(format!("`{}` is read here", decl.name))
+                .with_secondary(why.span, why.message)
+        } else {
+            Diagnostic::error("E0701", format!("`{}` may be used before it is assigned", decl.name), span)
+                .with_label(format!("`{}` is read here", decl.name))
+                .with_secondary(decl.span, format!("`{}` is declared here without a value", decl.name))
+                .with_secondary(why.span, why.message)
+        };
+        self.diagnostics.push(diag.with_note(format!("assign `{}` on every path before reading it", decl.name)));
+    }
+}
@@ -1,192 +1,740 @@
-#[derive(Debug, PartialEq)]
+use std::borrow::Cow;
+
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::types::IntTy;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 
 pub enum TokenType {
-    Num, Add, Sub, Div, Mul, Dot, True,
+    Num, Add, Sub, Div, Mul, True,
     Opt, Cpt, Ocl, Ccl, Scln, Equ, False, Eof,
     Eqv, Gre, Les, Geq, Leq, Break, Str, Mod,
-    Loop, If, Elif, Else, Func, Slash, Return,
-    Iden, Qt, And, Or, Let, NewLine, Com
+    Loop, If, Elif, Else, Func, Return,
+    Iden, And, Or, Let, Com,
+    Neq, Not, Amp, Pipe, Caret, Tilde, Shl, Shr,
+    Cln, Arrow, Osq, Csq, Mut, Type, Char, Doc, Const, As,
+
+    // Only produced in trivia mode. `Unknown` covers source text the lexer
+    // reported an error for and never turned into a token.
+    Whitespace, Comment, Unknown,
+}
+
+impl TokenType {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenType::Whitespace | TokenType::Comment | TokenType::Unknown)
+    }
 }
 
+// `value` borrows from the source. The only owned values are string
+// literals whose escapes had to be decoded.
 #[derive(Debug)]
-pub struct Token {
+pub struct Token<'a> {
     pub ttype: TokenType,
-    pub value: String,
+    pub value: Cow<'a, str>,
+    pub span: Span,
 }
 
-pub fn lex(file_buffer: &str, pos: &mut usize) -> Option<Token> {
-    let chars: Vec<char> = file_buffer.chars().collect();
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnknownEscape(char),
+    UnterminatedEscape,
+    MalformedHexEscape,
+    HexEscapeOutOfRange,
+    MalformedUnicodeEscape { reason: &'static str },
+    EmptyChar,
+    MultiCharLiteral,
+    UnterminatedChar,
+    UnterminatedBlockComment { outermost: Span },
+    UnterminatedString { multiline: bool },
+    UnknownChar(char),
+    ExtraDecimalPoint { literal: Span },
+    InvalidDigit { digit: char, radix: u32 },
+    MissingDigits { prefix: &'static str },
+    InvalidSuffix { suffix: String, float: bool },
+}
+
+#[derive(Debug, Clone)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
 
-    while *pos < chars.len() {
-        if chars[*pos] == '#' {
-            while *pos < chars.len() && chars[*pos] != '\n' {
-                *pos += 1;
+impl From<LexError> for Diagnostic {
+    fn from(err: LexError) -> Diagnostic {
+        match err.kind {
+            LexErrorKind::UnknownEscape(c) => {
+                Diagnostic::error("E0001", format!("unknown character escape `\\{}`", c.escape_default()), err.span)
+                    .with_label("unknown escape")
+                    .with_note("supported escapes are `\\n`, `\\t`, `\\r`, `\\0`, `\\\\`, `\\'`, `\\\"`, `\\xNN` and `\\u{NNNN}`")
+            }
+            LexErrorKind::UnterminatedEscape => {
+                Diagnostic::error("E0002", "unterminated escape sequence", err.span)
+                    .with_label("file ends after this backslash")
+            }
+            LexErrorKind::MalformedHexEscape => {
+                Diagnostic::error("E0010", "invalid `\\x` escape", err.span)
+                    .with_label("expected two hex digits after `\\x`")
+            }
+            LexErrorKind::HexEscapeOutOfRange => {
+                Diagnostic::error("E0011", "`\\x` escape out of range", err.span)
+                    .with_label("must be at most `\\x7F`")
+                    .with_note("use `\\u{NN}` for characters outside ASCII")
+            }
+            LexErrorKind::MalformedUnicodeEscape { reason } => {
+                Diagnostic::error("E0012", "invalid unicode escape", err.span)
+                    .with_label(reason)
+                    .with_note("unicode escapes are written `\\u{1F600}`")
+            }
+            LexErrorKind::EmptyChar => {
+                Diagnostic::error("E0007", "empty character literal", err.span)
+                    .with_label("expected a character between the quotes")
+            }
+            LexErrorKind::MultiCharLiteral => {
+                Diagnostic::error("E0008", "character literal may only contain one character", err.span)
+                    .with_label("more than one character")
+                    .with_note("use double quotes for a string literal")
+            }
+            LexErrorKind::UnterminatedChar => {
+                Diagnostic::error("E0009", "unterminated character literal", err.span)
+                    .with_label("missing closing `'`")
+            }
+            LexErrorKind::UnterminatedBlockComment { outermost } => {
+                let diag = Diagnostic::error("E0013", "unterminated block comment", err.span)
+                    .with_label("this comment is never closed");
+                if outermost != err.span {
+                    diag.with_secondary(outermost, "nested inside this comment")
+                        .with_note("block comments nest, every `/*` needs its own `*/`")
+                } else {
+                    diag
+                }
+            }
+            LexErrorKind::UnterminatedString { multiline } => {
+                let diag = Diagnostic::error("E0014", "unterminated string literal", err.span)
+                    .with_label("string starts here and is never closed");
+                if multiline {
+                    diag.with_note("strings can span lines, so everything up to the end of the file was read into this one")
+                } else {
+                    diag
+                }
+            }
+            LexErrorKind::UnknownChar(c) => {
+                Diagnostic::error("E0015", format!("unknown character `{}`", c.escape_debug()), err.span)
+                    .with_label("not valid here")
+            }
+            LexErrorKind::ExtraDecimalPoint { literal } => {
+                Diagnostic::error("E0003", "number literal has more than one `.`", err.span)
+                    .with_label("second decimal point")
+                    .with_secondary(literal, "in this literal")
+            }
+            LexErrorKind::InvalidDigit { digit, radix } => {
+                Diagnostic::error("E0004", format!("invalid digit `{}` in base {} literal", digit, radix), err.span)
+                    .with_label(format!("not a base {} digit", radix))
+            }
+            LexErrorKind::MissingDigits { prefix } => {
+                Diagnostic::error("E0005", format!("no digits after `{}`", prefix), err.span)
+                    .with_label("expected at least one digit")
+            }
+            LexErrorKind::InvalidSuffix { suffix, float } => {
+                let diag = Diagnostic::error("E0006", format!("invalid suffix `{}` on number literal", suffix), err.span)
+                    .with_label("invalid suffix");
+                if float {
+                    diag.with_note("float literals cannot have a suffix")
+                } else {
+                    diag.with_note("valid suffixes are `u8`, `u16`, `u32`, `u64`, `i8`, `i16`, `i32` and `i64`")
+                }
             }
-            continue;
         }
+    }
+}
+
+// Source position saved at the start of a token: byte offset, line, column.
+type Mark = (usize, usize, usize);
+
+// Single pass over the source. Positions are byte offsets into `src`, line
+// and column are tracked as characters are consumed so building a span is
+// constant time.
+pub struct Lexer<'a> {
+    src: &'a str,
+    file: usize,
+    pos: usize,
+    line: usize,
+    column: usize,
+    finished: bool,
+    trivia: bool,
+}
 
-        if chars[*pos].is_whitespace() {
-            *pos += 1;
-            continue;
+impl<'a> Lexer<'a> {
+
+    pub fn new(src: &'a str, file: usize) -> Self {
+        return Self {
+            src,
+            file,
+            pos: 0,
+            line: 1,
+            column: 1,
+            finished: false,
+            trivia: false,
+        };
+    }
+
+    // Lexer that also yields whitespace and comments as `Whitespace` and
+    // `Comment` tokens, so the tokens cover the source without gaps.
+    pub fn with_trivia(src: &'a str, file: usize) -> Self {
+        let mut lexer = Self::new(src, file);
+        lexer.trivia = true;
+        return lexer;
+    }
+
+    fn peek(&self) -> Option<char> {
+        return self.src[self.pos..].chars().next();
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
         }
+        return Some(c);
+    }
 
-        let tok = match chars[*pos] {
-            '+' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Add, value: "+".to_string() })
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            return true;
+        }
+        return false;
+    }
+
+    fn mark(&self) -> Mark {
+        return (self.pos, self.line, self.column);
+    }
+
+    // Span from `mark` to the current position.
+    fn span_from(&self, mark: Mark) -> Span {
+        return Span {
+            file: self.file,
+            line: mark.1,
+            column: mark.2,
+            start: mark.0,
+            end: self.pos,
+        };
+    }
+
+    fn token(&self, ttype: TokenType, mark: Mark) -> Token<'a> {
+        return Token {
+            ttype,
+            value: Cow::Borrowed(&self.src[mark.0..self.pos]),
+            span: self.span_from(mark),
+        };
+    }
+
+    fn at(&self, text: &str) -> bool {
+        return self.src[self.pos..].starts_with(text);
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
             }
-            '-' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Sub, value: "-".to_string() })
+            self.bump();
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), LexError> {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                self.skip_line();
+            } else if self.at("/*") {
+                self.skip_block_comment()?;
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
             }
-            '*' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Mul, value: "*".to_string() })
+        }
+        return Ok(());
+    }
+
+    // Trivia mode counterpart of `skip_whitespace_and_comments`: lexes one
+    // run of whitespace or one comment, if there is one.
+    fn lex_trivia(&mut self) -> Result<Option<Token<'a>>, LexError> {
+        let mark = self.mark();
+
+        let ttype = match self.peek() {
+            Some('#') => {
+                self.skip_line();
+                TokenType::Comment
             }
-            '/' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Div, value: "/".to_string() })
+            Some(_) if self.at("/*") => {
+                self.skip_block_comment()?;
+                TokenType::Comment
             }
-            '%' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Mod, value: "%".to_string() })
+            Some(c) if c.is_whitespace() => {
+                while self.peek().is_some_and(|c| c.is_whitespace()) {
+                    self.bump();
+                }
+                TokenType::Whitespace
             }
-            '(' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Opt, value: "(".to_string() })
+            _ => return Ok(None),
+        };
+
+        return Ok(Some(self.token(ttype, mark)));
+    }
+
+    // Block comments nest, so a stack of opening positions is kept to point
+    // at the innermost one left open when the file ends.
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let outermost = self.mark();
+        let mut open = vec![outermost];
+        self.bump();
+        self.bump();
+
+        while let Some(start) = open.last().copied() {
+            if self.at("/*") {
+                open.push(self.mark());
+                self.bump();
+                self.bump();
+            } else if self.at("*/") {
+                open.pop();
+                self.bump();
+                self.bump();
+            } else if self.bump().is_none() {
+                let opening = Span { end: start.0 + 2, ..self.span_from(start) };
+                let outer = Span { end: outermost.0 + 2, ..self.span_from(outermost) };
+                return Err(LexError {
+                    kind: LexErrorKind::UnterminatedBlockComment { outermost: outer },
+                    span: opening,
+                });
             }
-            ')' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Cpt, value: ")".to_string() })
+        }
+
+        return Ok(());
+    }
+
+    // Called after the first `/` of `///`. The value is the text after the
+    // slashes up to the end of the line.
+    fn lex_doc_comment(&mut self, mark: Mark) -> Token<'a> {
+        self.bump();
+        self.bump();
+        let text_start = self.pos;
+        self.skip_line();
+
+        return Token {
+            ttype: TokenType::Doc,
+            value: Cow::Borrowed(&self.src[text_start..self.pos]),
+            span: self.span_from(mark),
+        };
+    }
+
+    // Called at a backslash. Consumes the whole escape sequence and returns
+    // the character it stands for.
+    fn lex_escape(&mut self) -> Result<char, LexError> {
+        let mark = self.mark();
+        self.bump(); // backslash
+
+        let c = match self.bump() {
+            Some(c) => c,
+            None => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnterminatedEscape,
+                    span: self.span_from(mark),
+                });
             }
-            '{' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Ocl, value: "{".to_string() })
+        };
+
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '\'' => Ok('\''),
+            '\"' => Ok('\"'),
+
+            'x' => {
+                let mut value = 0;
+                for _ in 0..2 {
+                    match self.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            self.bump();
+                            value = value * 16 + digit;
+                        }
+                        None => {
+                            return Err(LexError {
+                                kind: LexErrorKind::MalformedHexEscape,
+                                span: self.span_from(mark),
+                            });
+                        }
+                    }
+                }
+
+                if value > 0x7F {
+                    return Err(LexError {
+                        kind: LexErrorKind::HexEscapeOutOfRange,
+                        span: self.span_from(mark),
+                    });
+                }
+                Ok(char::from(value as u8))
             }
-            '}' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Ccl, value: "}".to_string() })
+
+            'u' => {
+                let malformed = |lexer: &Self, reason| LexError {
+                    kind: LexErrorKind::MalformedUnicodeEscape { reason },
+                    span: lexer.span_from(mark),
+                };
+
+                if !self.eat('{') {
+                    return Err(malformed(self, "expected `{` after `\\u`"));
+                }
+
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = self.peek().and_then(|c| c.to_digit(16)) {
+                    self.bump();
+                    digits += 1;
+                    value = value.saturating_mul(16).saturating_add(digit);
+                }
+
+                if !self.eat('}') {
+                    return Err(malformed(self, "expected `}` to close the escape"));
+                }
+                if digits == 0 {
+                    return Err(malformed(self, "empty unicode escape"));
+                }
+                if digits > 6 {
+                    return Err(malformed(self, "unicode escapes have at most 6 hex digits"));
+                }
+
+                match char::from_u32(value) {
+                    Some(c) => Ok(c),
+                    None => Err(malformed(self, "not a valid unicode scalar value")),
+                }
             }
-            ',' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Com, value: ",".to_string() })
+
+            other => {
+                Err(LexError {
+                    kind: LexErrorKind::UnknownEscape(other),
+                    span: self.span_from(mark),
+                })
             }
-            ';' => {
-                *pos += 1;
-                Some(Token { ttype: TokenType::Scln, value: ";".to_string() })
+        }
+    }
+
+    // Called after the opening quote. The literal stays borrowed unless it
+    // contains an escape. A bad escape does not stop the scan, so lexing
+    // resumes after the closing quote.
+    fn lex_string(&mut self, mark: Mark) -> Result<Token<'a>, LexError> {
+        let content_start = self.pos;
+        let mut decoded: Option<String> = None;
+        let mut error: Option<LexError> = None;
+
+        while let Some(c) = self.peek() {
+            if c == '\"' {
+                break;
             }
-            '\"' => {
-                *pos += 1;
-                let mut literal = String::new();
-                while *pos < chars.len() && chars[*pos] != '\"' {
-                    if chars[*pos] == '\\' {
-                        *pos += 1;
-                        if *pos >= chars.len() {
-                            return None;
-                        }
-                        match chars[*pos] {
-                            '\"' => {
-                                *pos += 1;
-                                literal.push('\"');
-                            }
-                            'n' => {
-                                *pos += 1;
-                                literal.push('\n');
-                            }
-                            '\\' => {
-                                *pos += 1;
-                                literal.push('\\');
-                            }
-                            _ => return None,
+
+            if c == '\\' {
+                let mut literal = decoded.take().unwrap_or_else(|| self.src[content_start..self.pos].to_string());
+                match self.lex_escape() {
+                    Ok(c) => literal.push(c),
+                    Err(err) => {
+                        if err.kind == LexErrorKind::UnterminatedEscape {
+                            return Err(err);
                         }
-                    } else {
-                        literal.push(chars[*pos]);
-                        *pos += 1;
+                        error = error.or(Some(err));
                     }
                 }
-                *pos += 1;
-                return Some(Token { ttype: TokenType::Str, value: literal });
+                decoded = Some(literal);
+            } else {
+                if let Some(literal) = decoded.as_mut() {
+                    literal.push(c);
+                }
+                self.bump();
             }
-            '=' => {
-                *pos += 1;
-                if *pos < chars.len() && chars[*pos] == '=' {
-                    *pos += 1;
-                    Some(Token { ttype: TokenType::Eqv, value: "==".to_string() })
-                } else {
-                    Some(Token { ttype: TokenType::Equ, value: "=".to_string() })
+        }
+
+        let content_end = self.pos;
+        if self.bump().is_none() {
+            let multiline = self.src[content_start..content_end].contains('\n');
+            return Err(LexError {
+                kind: LexErrorKind::UnterminatedString { multiline },
+                span: Span { end: mark.0 + 1, ..self.span_from(mark) },
+            });
+        }
+
+        if let Some(err) = error {
+            return Err(err);
+        }
+
+        let value = match decoded {
+            Some(literal) => Cow::Owned(literal),
+            None => Cow::Borrowed(&self.src[content_start..content_end]),
+        };
+
+        return Ok(Token { ttype: TokenType::Str, value, span: self.span_from(mark) });
+    }
+
+    // Called after the opening `'`. The token's value is the single
+    // character the literal stands for. A malformed literal is consumed up to
+    // its closing quote when there is one on the same line.
+    fn lex_char(&mut self, mark: Mark) -> Result<Token<'a>, LexError> {
+        let content_start = self.pos;
+
+        let value = match self.peek() {
+            None | Some('\n') => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnterminatedChar,
+                    span: self.span_from(mark),
+                });
+            }
+            Some('\'') => {
+                self.bump();
+                return Err(LexError {
+                    kind: LexErrorKind::EmptyChar,
+                    span: self.span_from(mark),
+                });
+            }
+            Some('\\') => match self.lex_escape() {
+                Ok(c) => Cow::Owned(c.to_string()),
+                Err(err) => {
+                    self.eat('\'');
+                    return Err(err);
                 }
+            },
+            Some(_) => {
+                self.bump();
+                Cow::Borrowed(&self.src[content_start..self.pos])
             }
-            '<' => {
-                *pos += 1;
-                if *pos < chars.len() && chars[*pos] == '=' {
-                    *pos += 1;
-                    Some(Token { ttype: TokenType::Leq, value: "<=".to_string() })
-                } else {
-                    Some(Token { ttype: TokenType::Les, value: "<".to_string() })
+        };
+
+        if self.eat('\'') {
+            return Ok(Token { ttype: TokenType::Char, value, span: self.span_from(mark) });
+        }
+
+        let rest = &self.src[self.pos..];
+        let line_end = rest.find('\n').unwrap_or(rest.len());
+
+        match rest[..line_end].find('\'') {
+            Some(close) => {
+                let end = self.pos + close + 1;
+                while self.pos < end {
+                    self.bump();
                 }
+                return Err(LexError {
+                    kind: LexErrorKind::MultiCharLiteral,
+                    span: self.span_from(mark),
+                });
             }
-            '>' => {
-                *pos += 1;
-                if *pos < chars.len() && chars[*pos] == '=' {
-                    *pos += 1;
-                    Some(Token { ttype: TokenType::Geq, value: ">=".to_string() })
-                } else {
-                    Some(Token { ttype: TokenType::Gre, value: ">".to_string() })
+            None => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnterminatedChar,
+                    span: self.span_from(mark),
+                });
+            }
+        }
+    }
+
+    // Called after the first digit. Accepts `0x`, `0b` and `0o` prefixes,
+    // `_` separators anywhere after the first digit and an integer type
+    // suffix. The whole literal is consumed even if it is malformed, and the
+    // first problem found is reported.
+    fn lex_number(&mut self, first: char, mark: Mark) -> Result<Token<'a>, LexError> {
+        let mut radix = 10;
+        let mut digits = 1;
+        let mut prefix = "";
+
+        if first == '0' {
+            match self.peek() {
+                Some('x') => { radix = 16; prefix = "0x"; }
+                Some('b') => { radix = 2; prefix = "0b"; }
+                Some('o') => { radix = 8; prefix = "0o"; }
+                _ => {}
+            }
+            if radix != 10 {
+                self.bump();
+                digits = 0;
+            }
+        }
+
+        let mut float = false;
+        let mut error: Option<LexError> = None;
+
+        while let Some(c) = self.peek() {
+            let digit_mark = self.mark();
+
+            if c == '_' {
+                self.bump();
+            } else if c == '.' && radix == 10 {
+                self.bump();
+                if float {
+                    let span = self.span_from(digit_mark);
+                    error = error.or(Some(LexError {
+                        kind: LexErrorKind::ExtraDecimalPoint { literal: span },
+                        span,
+                    }));
                 }
+                float = true;
+            } else if c.is_digit(radix) {
+                self.bump();
+                digits += 1;
+            } else if c.is_ascii_digit() {
+                self.bump();
+                error = error.or(Some(LexError {
+                    kind: LexErrorKind::InvalidDigit { digit: c, radix },
+                    span: self.span_from(digit_mark),
+                }));
+            } else {
+                break;
+            }
+        }
+
+        let suffix_mark = self.mark();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            self.bump();
+        }
+        let suffix = &self.src[suffix_mark.0..self.pos];
+
+        if let Some(mut err) = error {
+            if let LexErrorKind::ExtraDecimalPoint { literal } = &mut err.kind {
+                *literal = self.span_from(mark);
             }
-            _ => None,
+            return Err(err);
+        }
+
+        if digits == 0 {
+            return Err(LexError {
+                kind: LexErrorKind::MissingDigits { prefix },
+                span: self.span_from(mark),
+            });
+        }
+
+        if !suffix.is_empty() && (float || IntTy::from_name(suffix).is_none()) {
+            return Err(LexError {
+                kind: LexErrorKind::InvalidSuffix { suffix: suffix.to_string(), float },
+                span: self.span_from(suffix_mark),
+            });
+        }
+
+        return Ok(self.token(TokenType::Num, mark));
+    }
+
+    fn lex_word(&mut self, mark: Mark) -> Token<'a> {
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            self.bump();
+        }
+
+        let token_type = match &self.src[mark.0..self.pos] {
+            "loop" => TokenType::Loop,
+            "if" => TokenType::If,
+            "elif" => TokenType::Elif,
+            "else" => TokenType::Else,
+            "true" => TokenType::True,
+            "false" => TokenType::False,
+            "break" => TokenType::Break,
+            "return" => TokenType::Return,
+            "fn" => TokenType::Func,
+            "and" => TokenType::And,
+            "or" => TokenType::Or,
+            "let" => TokenType::Let,
+            "mut" => TokenType::Mut,
+            "type" => TokenType::Type,
+            "const" => TokenType::Const,
+            "as" => TokenType::As,
+            _ => TokenType::Iden,
         };
 
-        if let Some(tok) = tok {
-            return Some(tok);
+        return self.token(token_type, mark);
+    }
+
+    fn next_token(&mut self) -> Result<Token<'a>, LexError> {
+        if self.trivia {
+            if let Some(token) = self.lex_trivia()? {
+                return Ok(token);
+            }
+        } else {
+            self.skip_whitespace_and_comments()?;
         }
 
-        if chars[*pos].is_ascii_digit() {
-            let mut val = String::new();
-            let mut float = false;
-            while *pos < chars.len() && (chars[*pos].is_ascii_digit() || chars[*pos] == '.') {
-                if chars[*pos] == '.' {
-                    if float {
-                        return None;
-                    }
-                    float = true;
-                }
-                val.push(chars[*pos]);
-                *pos += 1;
-            }
-            return Some(Token { ttype: TokenType::Num, value: val });
-        } else if chars[*pos].is_ascii_alphabetic() || chars[*pos] == '_' {
-            let mut val = String::new();
-            val.push(chars[*pos]);
-            *pos += 1;
-            while *pos < chars.len() && (chars[*pos].is_ascii_alphanumeric() || chars[*pos] == '_') {
-                val.push(chars[*pos]);
-                *pos += 1;
-            }
-            let token_type = match val.as_str() {
-                "loop" => TokenType::Loop,
-                "if" => TokenType::If,
-                "elif" => TokenType::Elif,
-                "else" => TokenType::Else,
-                "true" => TokenType::True,
-                "false" => TokenType::False,
-                "break" => TokenType::Break,
-                "return" => TokenType::Return,
-                "fn" => TokenType::Func,
-                "and" => TokenType::And,
-                "or" => TokenType::Or,
-                "let" => TokenType::Let,
-                _ => TokenType::Iden,
-            };
-            return Some(Token { ttype: token_type, value: val });
-        }
-
-        *pos += 1;
-    }
-
-    Some(Token {
-        ttype: TokenType::Eof,
-        value: String::new(),
-    })
+        let mark = self.mark();
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Ok(self.token(TokenType::Eof, mark)),
+        };
+
+        let ttype = match c {
+            '+' => TokenType::Add,
+            '-' => if self.eat('>') { TokenType::Arrow } else { TokenType::Sub },
+            '*' => TokenType::Mul,
+            '/' if self.at("//") => return Ok(self.lex_doc_comment(mark)),
+            '/' => TokenType::Div,
+            '%' => TokenType::Mod,
+            '(' => TokenType::Opt,
+            ')' => TokenType::Cpt,
+            '{' => TokenType::Ocl,
+            '}' => TokenType::Ccl,
+            ',' => TokenType::Com,
+            ':' => TokenType::Cln,
+            '[' => TokenType::Osq,
+            ']' => TokenType::Csq,
+            ';' => TokenType::Scln,
+            '\"' => return self.lex_string(mark),
+            '\'' => return self.lex_char(mark),
+            '&' => TokenType::Amp,
+            '|' => TokenType::Pipe,
+            '^' => TokenType::Caret,
+            '~' => TokenType::Tilde,
+            '=' => if self.eat('=') { TokenType::Eqv } else { TokenType::Equ },
+            '!' => if self.eat('=') { TokenType::Neq } else { TokenType::Not },
+            '<' => {
+                if self.eat('=') { TokenType::Leq }
+                else if self.eat('<') { TokenType::Shl }
+                else { TokenType::Les }
+            }
+            '>' => {
+                if self.eat('=') { TokenType::Geq }
+                else if self.eat('>') { TokenType::Shr }
+                else { TokenType::Gre }
+            }
+            c if c.is_ascii_digit() => return self.lex_number(c, mark),
+            c if c.is_ascii_alphabetic() || c == '_' => return Ok(self.lex_word(mark)),
+            _ => {
+                return Err(LexError {
+                    kind: LexErrorKind::UnknownChar(c),
+                    span: self.span_from(mark),
+                });
+            }
+        };
+
+        return Ok(self.token(ttype, mark));
+    }
 }
 
+// Yields every token of the source followed by a single Eof token.
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.next_token();
+        if let Ok(Token { ttype: TokenType::Eof, .. }) = result {
+            self.finished = true;
+        }
+
+        return Some(result);
+    }
+}
@@ -0,0 +1,475 @@
+use crate::cst::{NodeKind, SyntaxElement, SyntaxNode, SyntaxToken};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Lexer, TokenType};
+use crate::parser::{parse_int_literal, ASTNode, ConstDecl, Function, Item, Module, Param, TypeDecl, TypeExpr, VarDecl};
+use crate::span::Span;
+use crate::types::IntTy;
+
+// Derives the AST from the lossless syntax tree. The parser only checks the
+// shape of the source and builds the tree, everything the later passes need
+// is read back from it here: names and literal values from token text, spans
+// from the spans the tokens carry. `Error` nodes left by syntax errors become
+// `ASTNode::Error` inside a block and `Item::Error` at the top level.
+//
+// Nodes other than `Error` are only closed once their production parsed
+// completely, so their children are the ones the grammar promises.
+
+pub fn lower(tree: &SyntaxNode, src: &str, file: usize) -> (Module, Vec<Diagnostic>) {
+    let mut lowerer = Lowerer {
+        file,
+        line_starts: std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(index, _)| index + 1))
+            .collect(),
+        src,
+        diagnostics: Vec::new(),
+    };
+
+    let mut module = Module {
+        items: Vec::new(),
+        top_level_statements: Vec::new(),
+    };
+
+    for node in nodes(tree) {
+        match lowerer.statement(&node) {
+            ASTNode::FuncDef(func) => module.items.push(Item::Function(func)),
+            ASTNode::VarDecNode(var) => module.items.push(Item::Global(var)),
+            ASTNode::ConstDecl(constant) => module.items.push(Item::Const(constant)),
+            ASTNode::TypeDecl(decl) => module.items.push(Item::Type(decl)),
+            ASTNode::Error(span) => module.items.push(Item::Error(span)),
+            statement => module.top_level_statements.push(statement),
+        }
+    }
+
+    return (module, lowerer.diagnostics);
+}
+
+// Direct child nodes of `node`.
+fn nodes(node: &SyntaxNode) -> Vec<SyntaxNode> {
+    return node
+        .children()
+        .into_iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+        .collect();
+}
+
+// Direct child tokens of `node`, without trivia.
+fn tokens(node: &SyntaxNode) -> Vec<SyntaxToken> {
+    return node
+        .children()
+        .into_iter()
+        .filter_map(|child| match child {
+            SyntaxElement::Token(token) if !token.kind().is_trivia() => Some(token),
+            _ => None,
+        })
+        .collect();
+}
+
+fn token(node: &SyntaxNode, kind: TokenType) -> Option<SyntaxToken> {
+    return tokens(node).into_iter().find(|token| token.kind() == kind);
+}
+
+fn child(node: &SyntaxNode, kind: NodeKind) -> Option<SyntaxNode> {
+    return nodes(node).into_iter().find(|child| child.kind() == kind);
+}
+
+// The `///` lines directly before a declaration. One leading space is
+// dropped from each line and the lines are joined with `\n`.
+fn doc(node: &SyntaxNode) -> Option<String> {
+    let lines: Vec<String> = tokens(node)
+        .iter()
+        .filter(|token| token.kind() == TokenType::Doc)
+        .map(|token| {
+            let text = &token.text()[3..];
+            text.strip_prefix(' ').unwrap_or(text).to_string()
+        })
+        .collect();
+
+    if lines.is_empty() {
+        return None;
+    }
+    return Some(lines.join("\n"));
+}
+
+struct Lowerer<'a> {
+    src: &'a str,
+    file: usize,
+    // Byte offset at which each line starts.
+    line_starts: Vec<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Lowerer<'a> {
+
+    // Empty span at `offset`, for a node without tokens. Only those need
+    // the column counted here, every token carries its own span.
+    fn empty_span(&self, offset: usize) -> Span {
+        let line = self.line_starts.partition_point(|&line_start| line_start <= offset);
+        let line_start = self.line_starts[line - 1];
+
+        return Span {
+            file: self.file,
+            line,
+            column: self.src[line_start..offset].chars().count() + 1,
+            start: offset,
+            end: offset,
+        };
+    }
+
+    // From the first to the last token of `node`. Doc comments are left out
+    // unless `docs` is set, as they are not part of the declaration itself.
+    fn node_span(&self, node: &SyntaxNode, docs: bool) -> Span {
+        let keep = |kind: TokenType| !kind.is_trivia() && (docs || kind != TokenType::Doc);
+
+        match (node.first_token_span(keep), node.last_token_span(keep)) {
+            (Some(first), Some(last)) => return first.to(last),
+            _ => {
+                let (start, _) = node.range();
+                return self.empty_span(start);
+            }
+        }
+    }
+
+    fn span(&self, node: &SyntaxNode) -> Span {
+        return self.node_span(node, false);
+    }
+
+    // From the start of `start` to the last token of `node`.
+    fn span_from(&self, start: Span, node: &SyntaxNode) -> Span {
+        match node.last_token_span(|kind| !kind.is_trivia()) {
+            Some(last) => return start.to(last),
+            None => return start,
+        }
+    }
+
+    fn name(&self, node: &SyntaxNode) -> (String, Span) {
+        let name = token(node, TokenType::Iden).expect("declaration without a name");
+        return (name.text().to_string(), name.span());
+    }
+
+    fn statement(&mut self, node: &SyntaxNode) -> ASTNode {
+        let children = nodes(node);
+
+        match node.kind() {
+            NodeKind::VarDec => {
+                let (name, name_span) = self.name(node);
+                let ty = child(node, NodeKind::Type).map(|ty| self.type_expr(&ty));
+                let value = children
+                    .iter()
+                    .find(|child| child.kind() != NodeKind::Type)
+                    .map(|value| Box::new(self.expr(value)));
+
+                return ASTNode::VarDecNode(VarDecl {
+                    doc: doc(node),
+                    name,
+                    name_span,
+                    mutable: token(node, TokenType::Mut).is_some(),
+                    ty,
+                    value,
+                    span: self.span(node),
+                });
+            }
+
+            NodeKind::ConstDecl => {
+                let (name, name_span) = self.name(node);
+                return ASTNode::ConstDecl(ConstDecl {
+                    doc: doc(node),
+                    name,
+                    name_span,
+                    ty: self.type_expr(&children[0]),
+                    value: Box::new(self.expr(&children[1])),
+                    span: self.span(node),
+                });
+            }
+
+            NodeKind::TypeDecl => {
+                let (name, name_span) = self.name(node);
+                return ASTNode::TypeDecl(TypeDecl {
+                    doc: doc(node),
+                    name,
+                    name_span,
+                    ty: self.type_expr(&children[0]),
+                    span: self.span(node),
+                });
+            }
+
+            NodeKind::FuncDef => {
+                let (name, name_span) = self.name(node);
+                let arguments = match child(node, NodeKind::ParamList) {
+                    Some(list) => nodes(&list).iter().map(|param| self.param(param)).collect(),
+                    None => Vec::new(),
+                };
+
+                return ASTNode::FuncDef(Function {
+                    doc: doc(node),
+                    name,
+                    name_span,
+                    arguments,
+                    ret_type: child(node, NodeKind::Type).map(|ty| self.type_expr(&ty)),
+                    block: self.block(node),
+                    span: self.span(node),
+                });
+            }
+
+            NodeKind::If => {
+                let mut elif_branch = Vec::new();
+                let mut else_branch = None;
+
+                for clause in &children {
+                    match clause.kind() {
+                        NodeKind::ElifClause => {
+                            elif_branch.push((Box::new(self.condition(clause)), self.block(clause)));
+                        }
+                        NodeKind::ElseClause => else_branch = Some(self.block(clause)),
+                        _ => {}
+                    }
+                }
+
+                return ASTNode::IfElseNode {
+                    condition: Box::new(self.condition(node)),
+                    then_branch: self.block(node),
+                    elif_branch,
+                    else_branch,
+                    span: self.span(node),
+                };
+            }
+
+            NodeKind::Loop => {
+                return ASTNode::LoopNode {
+                    condition: Box::new(self.condition(node)),
+                    block: self.block(node),
+                    span: self.span(node),
+                };
+            }
+
+            NodeKind::Break => return ASTNode::BreakNode(self.span(node)),
+
+            NodeKind::Return => {
+                let value = children.first().map(|value| Box::new(self.expr(value)));
+                return ASTNode::ReturnNode(value, self.span(node));
+            }
+
+            NodeKind::Assign => {
+                let target = self.expr(&children[0]);
+                let value = self.expr(&children[1]);
+
+                if !target.is_place() {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0113", "invalid left-hand side of assignment", target.span())
+                            .with_label("cannot assign to this expression")
+                            .with_note("only variables, dereferenced pointers and array elements can be assigned to"),
+                    );
+                }
+
+                let span = self.span_from(target.span(), node);
+                return ASTNode::AssignNode {
+                    target: Box::new(target),
+                    value: Box::new(value),
+                    span,
+                };
+            }
+
+            NodeKind::ExprStmt => return self.expr(&children[0]),
+
+            // Spans the tokens skipped while recovering, doc comments
+            // included.
+            _ => return ASTNode::Error(self.node_span(node, true)),
+        }
+    }
+
+    // Statements of the `Block` directly under `node`.
+    fn block(&mut self, node: &SyntaxNode) -> Vec<ASTNode> {
+        let block = child(node, NodeKind::Block).expect("missing block");
+        return nodes(&block).iter().map(|statement| self.statement(statement)).collect();
+    }
+
+    // Expression of the `Condition` directly under `node`.
+    fn condition(&mut self, node: &SyntaxNode) -> ASTNode {
+        let condition = child(node, NodeKind::Condition).expect("missing condition");
+        return self.expr(&nodes(&condition)[0]);
+    }
+
+    fn param(&mut self, node: &SyntaxNode) -> Param {
+        let (name, name_span) = self.name(node);
+        return Param {
+            name,
+            name_span,
+            mutable: token(node, TokenType::Mut).is_some(),
+            ty: self.type_expr(&nodes(node)[0]),
+            span: self.span(node),
+        };
+    }
+
+    fn type_expr(&mut self, node: &SyntaxNode) -> TypeExpr {
+        let first = tokens(node).into_iter().next().expect("empty type");
+
+        match first.kind() {
+            TokenType::Mul => {
+                let pointee = self.type_expr(&nodes(node)[0]);
+                return TypeExpr::Pointer {
+                    mutable: token(node, TokenType::Mut).is_some(),
+                    span: first.span().to(pointee.span()),
+                    pointee: Box::new(pointee),
+                };
+            }
+
+            TokenType::Osq => {
+                let children = nodes(node);
+                return TypeExpr::Array {
+                    element: Box::new(self.type_expr(&children[0])),
+                    size: Box::new(self.expr(&children[1])),
+                    span: self.span(node),
+                };
+            }
+
+            _ => {
+                let span = first.span();
+                let name = first.text();
+                if let Some(ty) = IntTy::from_name(name) {
+                    return TypeExpr::Int(ty, span);
+                }
+                if name == "bool" {
+                    return TypeExpr::Bool(span);
+                }
+                return TypeExpr::Named(name.to_string(), span);
+            }
+        }
+    }
+
+    // Spans match what the parser used to give: an operator node spans its
+    // operands, so parentheses around an operand are not part of it. Spans
+    // are built from the operands where possible, looking up the first token
+    // of a deeply left-nested operand would be slow.
+    fn expr(&mut self, node: &SyntaxNode) -> ASTNode {
+        let children = nodes(node);
+        let first = tokens(node).into_iter().next();
+
+        match node.kind() {
+            NodeKind::Literal => return self.literal(&first.expect("empty literal")),
+
+            NodeKind::Name => {
+                let name = first.expect("empty name");
+                return ASTNode::Identifier(name.text().to_string(), name.span());
+            }
+
+            NodeKind::Paren => return self.expr(&children[0]),
+
+            NodeKind::UnaryOp | NodeKind::Deref | NodeKind::AddrOf => {
+                let op = first.expect("missing operator");
+                let operand = self.expr(&children[0]);
+                let span = op.span().to(operand.span());
+                let operand = Box::new(operand);
+
+                match node.kind() {
+                    NodeKind::Deref => return ASTNode::DerefNode { operand, span },
+                    NodeKind::AddrOf => {
+                        let mutable = token(node, TokenType::Mut).is_some();
+                        return ASTNode::AddrOfNode { mutable, operand, span };
+                    }
+                    _ => return ASTNode::UnaryOpNode { op: op.text().to_string(), operand, span },
+                }
+            }
+
+            NodeKind::BinOp => {
+                let op = first.expect("missing operator").text().to_string();
+                let left = self.expr(&children[0]);
+                let right = self.expr(&children[1]);
+                let span = left.span().to(right.span());
+
+                return ASTNode::BinOpNode {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                    span,
+                };
+            }
+
+            NodeKind::Cast => {
+                let value = self.expr(&children[0]);
+                let ty = self.type_expr(&children[1]);
+                let span = value.span().to(ty.span());
+
+                return ASTNode::CastNode {
+                    value: Box::new(value),
+                    ty,
+                    span,
+                };
+            }
+
+            NodeKind::Index => {
+                let base = self.expr(&children[0]);
+                let index = self.expr(&children[1]);
+                let span = self.span_from(base.span(), node);
+
+                return ASTNode::IndexNode {
+                    base: Box::new(base),
+                    index: Box::new(index),
+                    span,
+                };
+            }
+
+            NodeKind::Call => {
+                let name = first.expect("call without a name");
+                let arguments = nodes(&children[0]).iter().map(|argument| self.expr(argument)).collect();
+
+                return ASTNode::FuncCall {
+                    name: name.text().to_string(),
+                    name_span: name.span(),
+                    arguments,
+                    span: self.span(node),
+                };
+            }
+
+            NodeKind::ArrayLiteral => {
+                let elements = children.iter().map(|element| self.expr(element)).collect();
+                return ASTNode::ArrayLiteral { elements, span: self.span(node) };
+            }
+
+            NodeKind::ArrayRepeat => {
+                return ASTNode::ArrayRepeat {
+                    value: Box::new(self.expr(&children[0])),
+                    count: Box::new(self.expr(&children[1])),
+                    span: self.span(node),
+                };
+            }
+
+            _ => return ASTNode::Error(self.span(node)),
+        }
+    }
+
+    fn literal(&mut self, token: &SyntaxToken) -> ASTNode {
+        let text = token.text();
+        let span = token.span();
+
+        match token.kind() {
+            TokenType::True => return ASTNode::BoolNode(true, span),
+            TokenType::False => return ASTNode::BoolNode(false, span),
+
+            TokenType::Num if text.contains('.') => {
+                let num = text.replace('_', "").parse::<f64>().unwrap_or(0.0);
+                return ASTNode::FloatLiteral(num, span);
+            }
+
+            TokenType::Num => {
+                let (value, suffix) = parse_int_literal(text);
+                return ASTNode::IntLiteral(value, suffix, span);
+            }
+
+            // Escapes are decoded by lexing the literal again, it lexed
+            // without errors the first time.
+            _ => {
+                let value = match Lexer::new(text, self.file).next() {
+                    Some(Ok(token)) => token.value.into_owned(),
+                    _ => String::new(),
+                };
+
+                if token.kind() == TokenType::Char {
+                    return ASTNode::CharLiteral(value.chars().next().unwrap_or('\0'), span);
+                }
+                return ASTNode::StrLiteral(value, span);
+            }
+        }
+    }
+}
@@ -1,66 +1,249 @@
+// Functions end in an explicit `return`, like the rest of the code base.
+#![allow(clippy::needless_return)]
+// A `Diagnostic` is large, but it is only built once something went wrong,
+// so `Result<_, Diagnostic>` is passed by value rather than boxed.
+#![allow(clippy::result_large_err)]
+
+mod bigint;
+mod consteval;
+mod cst;
+mod diagnostic;
+mod entry;
+mod flow;
+mod fmt;
+mod init;
 mod lexer;
+mod lower;
+mod mutability;
 mod parser;
+mod resolve;
+mod span;
+mod typeck;
+mod types;
 
 use std::fs;
 use std::env;
+use std::io::Read;
+
+use cst::{SyntaxElement, SyntaxNode};
+use diagnostic::Diagnostic;
+use span::SourceMap;
+
+fn report(diag: &Diagnostic, sources: &SourceMap) {
+    eprintln!("{}", diagnostic::render(diag, sources));
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <filename> [--cst] [--entry <name>]", program);
+    eprintln!("       {} fmt [--check] [<filename> | -]", program);
+    std::process::exit(1);
+}
+
+// Parses `source` keeping comments and whitespace, or returns its syntax
+// errors.
+fn syntax_tree(source: &str, file: usize) -> Result<SyntaxNode, Vec<Diagnostic>> {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    for result in lexer::Lexer::with_trivia(source, file) {
+        match result {
+            Ok(tok) => tokens.push(tok),
+            Err(err) => errors.push(Diagnostic::from(err)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let (tree, _, diagnostics) = parser::Parser::new(tokens, source).parse_program();
+    errors.extend(diagnostics.into_iter().filter(|diag| diag.is_error()));
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(tree);
+}
+
+// `zerl fmt` rewrites the file in place, or prints the result when reading
+// from stdin. With `--check` nothing is written and the exit status says
+// whether the input was already formatted.
+fn run_fmt(program: &str, args: &[String]) -> std::io::Result<()> {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<&String> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.len() > 1 {
+        usage(program);
+    }
+
+    let path = paths.first().map_or("-", |path| path.as_str());
+    let (name, source) = if path == "-" {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)?;
+        ("<stdin>", source)
+    } else {
+        (path, fs::read_to_string(path)?)
+    };
+
+    let mut sources = SourceMap::new();
+    let file = sources.add(name, &source);
+
+    // A tree with syntax errors has no canonical layout, so only valid
+    // programs are formatted.
+    let tree = match syntax_tree(&source, file) {
+        Ok(tree) => tree,
+        Err(errors) => {
+            for diag in &errors {
+                report(diag, &sources);
+            }
+            std::process::exit(1);
+        }
+    };
+
+    let formatted = fmt::format(&tree);
+
+    // Formatting must never turn a valid program into an invalid one.
+    let output = sources.add(&format!("{} (formatted)", name), &formatted);
+    if let Err(errors) = syntax_tree(&formatted, output) {
+        eprintln!("internal error: formatting {} produced code that does not parse", name);
+        for diag in &errors {
+            report(diag, &sources);
+        }
+        std::process::exit(1);
+    }
+
+    if check {
+        if formatted != source {
+            eprintln!("{} is not formatted", name);
+            std::process::exit(1);
+        }
+    } else if path == "-" {
+        print!("{}", formatted);
+    } else if formatted != source {
+        fs::write(path, formatted)?;
+    }
+
+    return Ok(());
+}
 
 fn main() -> std::io::Result<()> {
 
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
-        eprintln!("Usage: {} <filename>", args[0]);
-        std::process::exit(1);
+        usage(&args[0]);
     }
 
-    let filename = &args[1];
-    let contents = fs::read_to_string(filename)?;
+    if args[1] == "fmt" {
+        return run_fmt(&args[0], &args[2..]);
+    }
 
+    let filename = &args[1];
+    let mut dump_cst = false;
+    let mut entry_name = entry::DEFAULT_ENTRY.to_string();
+
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--cst" => dump_cst = true,
+            "--entry" => match options.next() {
+                Some(name) => entry_name = name.clone(),
+                None => usage(&args[0]),
+            },
+            _ => usage(&args[0]),
+        }
+    }
     let file_buffer = fs::read_to_string(filename)?;
 
-    let mut pos = 0;
-    let mut token = lexer::lex(&file_buffer, &mut pos);
-    pos = pos + 1;
+    let mut sources = SourceMap::new();
+    let file = sources.add(filename, &file_buffer);
 
-    let mut pos = 0;
     let mut tokens = Vec::new();
-    loop {
-        match lexer::lex(&file_buffer, &mut pos) {
-            Some(tok) => {
+    let mut lex_errors = Vec::new();
+    let lexer = if dump_cst {
+        lexer::Lexer::with_trivia(&file_buffer, file)
+    } else {
+        lexer::Lexer::new(&file_buffer, file)
+    };
+
+    for result in lexer {
+        match result {
+            Ok(tok) => {
                 println!("{:?}", tok);
-                if tok.ttype == lexer::TokenType::Eof {
-                    tokens.push(tok);
-                    break;
-                }
                 tokens.push(tok);
             }
-            None => {
-                eprintln!("Lexing error near position {}", pos);
-                break;
+            Err(err) => {
+                lex_errors.push(Diagnostic::from(err));
             }
         }
     }
 
-    let mut parser = parser::Parser::new(tokens);
+    if !lex_errors.is_empty() {
+        for diag in &lex_errors {
+            report(diag, &sources);
+        }
+        std::process::exit(1);
+    }
 
-    println!("AST:");
+    let (tree, module, mut diagnostics) = parser::Parser::new(tokens, &file_buffer).parse_program();
+    debug_assert_eq!(tree.to_string(), file_buffer, "syntax tree does not reproduce the source");
 
-    while !parser.is_at_end() {
-        match parser.parse_statement() {
-            Some(ast) => {
-                pretty_print(&ast, "", true);
-            }
-            None => {
-                eprintln!("Parsing failed.");
-                break;
+    if dump_cst {
+        println!("CST:");
+        print_cst(&tree, 0);
+    }
+
+    println!("Items:");
+    for (i, item) in module.items.iter().enumerate() {
+        print_item(item, "", i == module.items.len() - 1);
+    }
+
+    println!("Top-level statements:");
+    for (i, ast) in module.top_level_statements.iter().enumerate() {
+        pretty_print(ast, "", i == module.top_level_statements.len() - 1);
+    }
+
+    // Later passes assume a well formed tree.
+    if !diagnostics.iter().any(|diag| diag.is_error()) {
+        let (entry_point, entry_diagnostics) = entry::check_entry(&module, file, &entry_name);
+        diagnostics.extend(entry_diagnostics);
+
+        if let Some(entry_point) = entry_point {
+            println!("Entry: {} after initializing {} globals", entry_point.entry.name, entry_point.init.len());
+        }
+
+        let (resolution, resolve_diagnostics) = resolve::resolve(&module);
+        diagnostics.extend(resolve_diagnostics);
+        diagnostics.extend(entry::check_global_order(&module, &resolution));
+        println!("Resolved {} uses of {} declarations", resolution.uses.len(), resolution.decls.len());
+
+        let (typing, type_diagnostics) = typeck::check(&module, &resolution);
+        diagnostics.extend(type_diagnostics);
+        diagnostics.extend(mutability::check(&module, &resolution, &typing));
+        diagnostics.extend(flow::check(&module, &typing));
+        diagnostics.extend(init::check(&module, &resolution, &typing));
+
+        println!("Types:");
+        for (i, decl) in resolution.decls.iter().enumerate() {
+            let id = resolve::DeclId(i);
+            if let Some(signature) = typing.functions.get(&id) {
+                println!("  {} {}: {}", decl.kind.describe(), decl.name, signature);
+            } else if let Some(value) = typing.consts.get(&id) {
+                println!("  {} {}: {} = {}", decl.kind.describe(), decl.name, typing.decls[&id], value);
+            } else if let Some(ty) = typing.decls.get(&id) {
+                println!("  {} {}: {}", decl.kind.describe(), decl.name, ty);
             }
         }
     }
 
+    for diag in &diagnostics {
+        report(diag, &sources);
+    }
+
+    if diagnostics.iter().any(|diag| diag.is_error()) {
+        std::process::exit(1);
+    }
+
     return Ok(());
 }
 
-use parser::ASTNode;
+use parser::{ASTNode, ConstDecl, Function, Item, TypeDecl, VarDecl};
 
 fn pretty_print(node: &ASTNode, prefix: &str, is_last: bool) {
     let connector = if is_last { "└── " } else { "├── " };
@@ -69,54 +252,110 @@ fn pretty_print(node: &ASTNode, prefix: &str, is_last: bool) {
 
     match node {
 
-        ASTNode::Eof => {
-            println!("End of file.");
+        ASTNode::Error(span) => {
+            println!("Error @ {}", span);
         }
 
-        ASTNode::Number(n) => {
-            println!("Number({})", n);
+        ASTNode::IntLiteral(n, suffix, span) => {
+            match suffix {
+                Some(ty) => println!("Int({}{}) @ {}", n, ty, span),
+                None => println!("Int({}) @ {}", n, span),
+            }
         }
 
-        ASTNode::Identifier(iden) => {
-            println!("Identifier({})", iden);
+        ASTNode::FloatLiteral(n, span) => {
+            println!("Float({}) @ {}", n, span);
         }
 
-        ASTNode::BoolNode(b) => {
-            println!("Bool({})", b);
+        ASTNode::Identifier(iden, span) => {
+            println!("Identifier({}) @ {}", iden, span);
         }
 
-        ASTNode::BreakNode => {
-            println!("Break");
+        ASTNode::BoolNode(b, span) => {
+            println!("Bool({}) @ {}", b, span);
         }
 
-        ASTNode::ReturnNode(expr_opt) => {
-            println!("Return");
+        ASTNode::BreakNode(span) => {
+            println!("Break @ {}", span);
+        }
+
+        ASTNode::ReturnNode(expr_opt, span) => {
+            println!("Return @ {}", span);
             if let Some(expr) = expr_opt {
                 let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
                 pretty_print(expr, &new_prefix, true);
             }
         }
 
-        ASTNode::StrLiteral(s) => {
-            println!("StrLiteral(\"{}\")", s);
+        ASTNode::StrLiteral(s, span) => {
+            println!("StrLiteral({:?}) @ {}", s, span);
         }
 
-        ASTNode::BinOpNode { op, left, right } => {
-            println!("BinOp('{}')", op);
+        ASTNode::UnaryOpNode { op, operand, span } => {
+            println!("UnaryOp('{}') @ {}", op, span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(operand, &new_prefix, true);
+        }
+
+        ASTNode::CharLiteral(c, span) => {
+            println!("CharLiteral({:?}) @ {}", c, span);
+        }
+
+        ASTNode::BinOpNode { op, left, right, span } => {
+            println!("BinOp('{}') @ {}", op, span);
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
             pretty_print(left, &new_prefix, false);
             pretty_print(right, &new_prefix, true);
         }
 
-        ASTNode::VarDecNode { name, value } => {
-            println!("VarDec({})", name);
+        ASTNode::VarDecNode(var) => print_var_decl(var, prefix, is_last),
+
+        ASTNode::ConstDecl(constant) => print_const_decl(constant, prefix, is_last),
+
+        ASTNode::DerefNode { operand, span } => {
+            println!("Deref @ {}", span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(operand, &new_prefix, true);
+        }
+
+        ASTNode::AddrOfNode { mutable, operand, span } => {
+            println!("AddrOf({}) @ {}", if *mutable { "mut" } else { "const" }, span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(operand, &new_prefix, true);
+        }
+
+        ASTNode::CastNode { value, ty, span } => {
+            println!("Cast({}) @ {}", ty, span);
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
             pretty_print(value, &new_prefix, true);
         }
 
-        ASTNode::AssignNode { name, value } => {
-            println!("Assign({})", name);
+        ASTNode::ArrayLiteral { elements, span } => {
+            println!("Array({}) @ {}", elements.len(), span);
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            for (i, element) in elements.iter().enumerate() {
+                pretty_print(element, &new_prefix, i == elements.len() - 1);
+            }
+        }
+
+        ASTNode::ArrayRepeat { value, count, span } => {
+            println!("ArrayRepeat @ {}", span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(value, &new_prefix, false);
+            pretty_print(count, &new_prefix, true);
+        }
+
+        ASTNode::IndexNode { base, index, span } => {
+            println!("Index @ {}", span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(base, &new_prefix, false);
+            pretty_print(index, &new_prefix, true);
+        }
+
+        ASTNode::AssignNode { target, value, span } => {
+            println!("Assign @ {}", span);
+            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            pretty_print(target, &new_prefix, false);
             pretty_print(value, &new_prefix, true);
         }
 
@@ -125,19 +364,20 @@ fn pretty_print(node: &ASTNode, prefix: &str, is_last: bool) {
             then_branch,
             elif_branch,
             else_branch,
+            span,
         } => {
-            println!("If");
+            println!("If @ {}", span);
 
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
             pretty_print(condition, &new_prefix, false);
 
-            println!("{}{}Then", new_prefix, "├── ");
+            println!("{}├── Then", new_prefix);
             for (i, stmt) in then_branch.iter().enumerate() {
                 pretty_print(stmt, &format!("{}│   ", new_prefix), i == then_branch.len() - 1 && elif_branch.is_empty() && else_branch.is_none());
             }
 
-            for (i, (elif_cond, elif_block)) in elif_branch.iter().enumerate() {
-                println!("{}{}Elif", new_prefix, "├── ");
+            for (elif_cond, elif_block) in elif_branch {
+                println!("{}├── Elif", new_prefix);
                 let elif_prefix = format!("{}│   ", new_prefix);
                 pretty_print(elif_cond, &elif_prefix, false);
                 for (j, stmt) in elif_block.iter().enumerate() {
@@ -146,15 +386,15 @@ fn pretty_print(node: &ASTNode, prefix: &str, is_last: bool) {
             }
 
             if let Some(else_block) = else_branch {
-                println!("{}{}Else", new_prefix, "└── ");
+                println!("{}└── Else", new_prefix);
                 for (i, stmt) in else_block.iter().enumerate() {
                     pretty_print(stmt, &format!("{}    ", new_prefix), i == else_block.len() - 1);
                 }
             }
         }
 
-        ASTNode::LoopNode { condition, block } => {
-            println!("Loop");
+        ASTNode::LoopNode { condition, block, span } => {
+            println!("Loop @ {}", span);
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
             pretty_print(condition, &new_prefix, false);
             for (i, stmt) in block.iter().enumerate() {
@@ -162,24 +402,102 @@ fn pretty_print(node: &ASTNode, prefix: &str, is_last: bool) {
             }
         }
 
-        ASTNode::FuncCall { name, arguments } => {
-            println!("FuncCall({})", name);
+        ASTNode::FuncCall { name, arguments, span, .. } => {
+            println!("FuncCall({}) @ {}", name, span);
             let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
             for (i, arg) in arguments.iter().enumerate() {
                 pretty_print(arg, &new_prefix, i == arguments.len() - 1);
             }
         }
 
-        ASTNode::FuncDef { name, arguments, block } => {
-            println!("FuncDef({})", name);
-            let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+        ASTNode::FuncDef(func) => print_function(func, prefix, is_last),
 
-            println!("{}├── Args: {:?}", new_prefix, arguments);
+        ASTNode::TypeDecl(decl) => print_type_decl(decl, prefix, is_last),
+    }
+}
 
-            for (i, stmt) in block.iter().enumerate() {
-                pretty_print(stmt, &new_prefix, i == block.len() - 1);
+fn print_item(item: &Item, prefix: &str, is_last: bool) {
+    print!("{}{}", prefix, if is_last { "└── " } else { "├── " });
+
+    match item {
+        Item::Function(func) => print_function(func, prefix, is_last),
+        Item::Global(var) => print_var_decl(var, prefix, is_last),
+        Item::Const(constant) => print_const_decl(constant, prefix, is_last),
+        Item::Type(decl) => print_type_decl(decl, prefix, is_last),
+        Item::Error(span) => println!("Error @ {}", span),
+    }
+}
+
+// The declaration printers continue a line `pretty_print` or `print_item`
+// has already started with the tree connector.
+
+fn print_var_decl(var: &VarDecl, prefix: &str, is_last: bool) {
+    let qualifier = if var.mutable { "mut " } else { "" };
+    match &var.ty {
+        Some(ty) => println!("VarDec({}{}: {}) @ {}", qualifier, var.name, ty, var.span),
+        None => println!("VarDec({}{}) @ {}", qualifier, var.name, var.span),
+    }
+    let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+    if let Some(doc) = &var.doc {
+        println!("{}├── Doc: {:?}", new_prefix, doc);
+    }
+    if let Some(value) = &var.value {
+        pretty_print(value, &new_prefix, true);
+    }
+}
+
+fn print_const_decl(constant: &ConstDecl, prefix: &str, is_last: bool) {
+    println!("Const({}: {}) @ {}", constant.name, constant.ty, constant.span);
+    let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+    if let Some(doc) = &constant.doc {
+        println!("{}├── Doc: {:?}", new_prefix, doc);
+    }
+    pretty_print(&constant.value, &new_prefix, true);
+}
+
+fn print_function(func: &Function, prefix: &str, is_last: bool) {
+    println!("FuncDef({}) @ {}", func.name, func.span);
+    let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+
+    if let Some(doc) = &func.doc {
+        println!("{}├── Doc: {:?}", new_prefix, doc);
+    }
+
+    let args: Vec<String> = func.arguments
+        .iter()
+        .map(|arg| format!("{}{}: {}", if arg.mutable { "mut " } else { "" }, arg.name, arg.ty))
+        .collect();
+    println!("{}├── Args: [{}]", new_prefix, args.join(", "));
+
+    if let Some(ret_type) = &func.ret_type {
+        println!("{}├── Returns: {}", new_prefix, ret_type);
+    }
+
+    for (i, stmt) in func.block.iter().enumerate() {
+        pretty_print(stmt, &new_prefix, i == func.block.len() - 1);
+    }
+}
+
+fn print_type_decl(decl: &TypeDecl, prefix: &str, is_last: bool) {
+    println!("TypeDecl({} = {}) @ {}", decl.name, decl.ty, decl.span);
+    if let Some(doc) = &decl.doc {
+        let new_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+        println!("{}└── Doc: {:?}", new_prefix, doc);
+    }
+}
+
+// One line per node and token, indented by depth, with byte ranges.
+fn print_cst(node: &SyntaxNode, depth: usize) {
+    let (start, end) = node.range();
+    println!("{}{:?} @ {}..{}", "  ".repeat(depth), node.kind(), start, end);
+
+    for child in node.children() {
+        match child {
+            SyntaxElement::Node(child) => print_cst(&child, depth + 1),
+            SyntaxElement::Token(token) => {
+                let (start, end) = token.range();
+                println!("{}{:?} @ {}..{} {:?}", "  ".repeat(depth + 1), token.kind(), start, end, token.text());
             }
         }
     }
 }
-
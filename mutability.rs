@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, Function, Item, Module, TypeExpr};
+use crate::resolve::{DeclKind, Resolution};
+use crate::span::Span;
+use crate::typeck::Typing;
+use crate::types::Type;
+
+// Only what is declared `mut` can be written: a variable or parameter needs
+// `mut` to be assigned or to have a `*mut` pointer taken to it, and writing
+// through a pointer needs a `*mut` pointer. Constants are never written.
+// A local declared without a value may still be assigned once, which the
+// initialization check enforces.
+
+// What a variable or parameter was declared with, for suggesting fixes.
+struct Binding<'m> {
+    ty: Option<&'m TypeExpr>,
+    value: Option<&'m ASTNode>,
+}
+
+struct Checker<'m> {
+    resolution: &'m Resolution,
+    typing: &'m Typing,
+    // Keyed by the span of the declared name.
+    bindings: HashMap<Span, Binding<'m>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Assign,
+    // `&mut place`
+    Borrow,
+}
+
+pub fn check(module: &Module, resolution: &Resolution, typing: &Typing) -> Vec<Diagnostic> {
+    let mut checker = Checker {
+        resolution,
+        typing,
+        bindings: HashMap::new(),
+        diagnostics: Vec::new(),
+    };
+
+    for item in &module.items {
+        match item {
+            Item::Function(func) => checker.check_function(func),
+            Item::Global(var) => {
+                checker.bindings.insert(var.name_span, Binding { ty: var.ty.as_ref(), value: var.value.as_deref() });
+                if let Some(value) = &var.value {
+                    checker.check_expr(value);
+                }
+            }
+            Item::Const(constant) => checker.check_expr(&constant.value),
+            Item::Type(_) | Item::Error(_) => {}
+        }
+    }
+
+    return checker.diagnostics;
+}
+
+// Empty span at the start of `span`, where text can be inserted.
+fn before(span: Span) -> Span {
+    return Span { end: span.start, ..span };
+}
+
+impl<'m> Checker<'m> {
+
+    fn check_function(&mut self, func: &'m Function) {
+        for param in &func.arguments {
+            self.bindings.insert(param.name_span, Binding { ty: Some(&param.ty), value: None });
+        }
+        self.check_block(&func.block);
+    }
+
+    fn check_block(&mut self, block: &'m [ASTNode]) {
+        for statement in block {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, node: &'m ASTNode) {
+        match node {
+            ASTNode::VarDecNode(var) => {
+                self.bindings.insert(var.name_span, Binding { ty: var.ty.as_ref(), value: var.value.as_deref() });
+                if let Some(value) = &var.value {
+                    self.check_expr(value);
+                }
+            }
+
+            ASTNode::ConstDecl(constant) => self.check_expr(&constant.value),
+
+            ASTNode::FuncDef(func) => self.check_function(func),
+
+            ASTNode::IfElseNode { condition, then_branch, elif_branch, else_branch, .. } => {
+                self.check_expr(condition);
+                self.check_block(then_branch);
+                for (condition, block) in elif_branch {
+                    self.check_expr(condition);
+                    self.check_block(block);
+                }
+                if let Some(block) = else_branch {
+                    self.check_block(block);
+                }
+            }
+
+            ASTNode::LoopNode { condition, block, .. } => {
+                self.check_expr(condition);
+                self.check_block(block);
+            }
+
+            ASTNode::ReturnNode(Some(value), _) => self.check_expr(value),
+
+            ASTNode::AssignNode { target, value, .. } => {
+                self.check_place(target, Access::Assign, target.span());
+                self.check_subexprs(target);
+                self.check_expr(value);
+            }
+
+            _ => self.check_expr(node),
+        }
+    }
+
+    fn check_expr(&mut self, node: &ASTNode) {
+        if let ASTNode::AddrOfNode { mutable: true, operand, span } = node {
+            self.check_place(operand, Access::Borrow, *span);
+        }
+        self.check_subexprs(node);
+    }
+
+    fn check_subexprs(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::FuncCall { arguments, .. } => {
+                for argument in arguments {
+                    self.check_expr(argument);
+                }
+            }
+
+            ASTNode::UnaryOpNode { operand, .. }
+            | ASTNode::DerefNode { operand, .. }
+            | ASTNode::AddrOfNode { operand, .. } => self.check_expr(operand),
+
+            ASTNode::CastNode { value, .. } => self.check_expr(value),
+
+            ASTNode::BinOpNode { left, right, .. } => {
+                self.check_expr(left);
+                self.check_expr(right);
+            }
+
+            ASTNode::IndexNode { base, index, .. } => {
+                self.check_expr(base);
+                self.check_expr(index);
+            }
+
+            ASTNode::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.check_expr(element);
+                }
+            }
+
+            ASTNode::ArrayRepeat { value, count, .. } => {
+                self.check_expr(value);
+                self.check_expr(count);
+            }
+
+            _ => {}
+        }
+    }
+
+    // Checks that `place` may be written, or pointed to by a `*mut`, by the
+    // expression at `span`. Anything that is not a place was reported by
+    // the parser or the type checker.
+    fn check_place(&mut self, place: &ASTNode, access: Access, span: Span) {
+        match place {
+            ASTNode::Identifier(name, name_span) => self.check_binding(name, *name_span, None, access, span),
+
+            ASTNode::DerefNode { operand, .. } => self.check_pointer(operand, access, span),
+
+            ASTNode::IndexNode { base, .. } => match self.typing.exprs.get(&base.span()) {
+                Some(Type::Pointer { .. }) => self.check_pointer(base, access, span),
+                _ => match base.as_ref() {
+                    ASTNode::Identifier(name, name_span) => {
+                        self.check_binding(name, *name_span, Some("an element of "), access, span)
+                    }
+                    _ => self.check_place(base, access, span),
+                },
+            },
+
+            _ => {}
+        }
+    }
+
+    // `part` says which part of the binding is written, for the message.
+    fn check_binding(&mut self, name: &str, name_span: Span, part: Option<&str>, access: Access, span: Span) {
+        let decl = match self.resolution.uses.get(&name_span) {
+            Some(&id) => self.resolution.decl(id),
+            None => return,
+        };
+        let deferred = decl.kind == DeclKind::Local
+            && self.bindings.get(&decl.span).is_some_and(|binding| binding.value.is_none());
+        if deferred && part.is_none() && matches!(access, Access::Assign) {
+            return;
+        }
+        let part = part.unwrap_or("");
+
+        let action = match access {
+            Access::Assign => format!("cannot assign to {}", part),
+            Access::Borrow => format!("cannot take a `*mut` pointer to {}", part),
+        };
+
+        match decl.kind {
+            DeclKind::Local | DeclKind::Global | DeclKind::Param if !decl.mutable => {
+                let what = decl.kind.describe();
+                self.diagnostics.push(
+                    Diagnostic::error("E0501", format!("{}immutable {} `{}`", action, what, name), span)
+                        .with_label(match access {
+                            Access::Assign => "cannot assign",
+                            Access::Borrow => "cannot point to it mutably",
+                        })
+                        .with_secondary(decl.span, format!("`{}` is not declared `mut`", name))
+                        .with_suggestion(before(decl.span), format!("consider making `{}` mutable", name), "mut "),
+                );
+            }
+
+            DeclKind::Const => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0502", format!("{}constant `{}`", action, name), span)
+                        .with_label("constants cannot change")
+                        .with_secondary(decl.span, format!("`{}` is declared `const`", name))
+                        .with_note("use `let mut` for a global that can be changed"),
+                );
+            }
+
+            _ => {}
+        }
+    }
+
+    // Checks that `pointer` is a `*mut`.
+    fn check_pointer(&mut self, pointer: &ASTNode, access: Access, span: Span) {
+        let pointee = match self.typing.exprs.get(&pointer.span()) {
+            Some(Type::Pointer { mutable: false, pointee }) => pointee,
+            _ => return,
+        };
+
+        let mut diag = match access {
+            Access::Assign => Diagnostic::error("E0503", "cannot write through a `*` pointer", span)
+                .with_label("written here"),
+            Access::Borrow => Diagnostic::error("E0503", "cannot take a `*mut` pointer through a `*` pointer", span)
+                .with_label("would allow writing through it"),
+        };
+        diag = diag.with_secondary(pointer.span(), format!("this is `*{}`, not `*mut {}`", pointee, pointee));
+
+        // Point at where the pointer's type comes from, if it is a variable.
+        let binding = match pointer {
+            ASTNode::Identifier(_, name_span) => self.resolution.uses.get(name_span)
+                .map(|&id| self.resolution.decl(id).span)
+                .and_then(|span| self.bindings.get(&span)),
+            _ => None,
+        };
+
+        match binding {
+            Some(Binding { ty: Some(TypeExpr::Pointer { mutable: false, pointee, span }), .. }) => {
+                diag = diag.with_suggestion(*span, "consider declaring a mutable pointer", format!("*mut {}", pointee));
+            }
+            Some(Binding { ty: None, value: Some(ASTNode::AddrOfNode { mutable: false, span, .. }) }) => {
+                let after_amp = Span { start: span.start + 1, end: span.start + 1, column: span.column + 1, ..*span };
+                diag = diag.with_suggestion(after_amp, "consider taking a mutable pointer", "mut ");
+            }
+            _ => {}
+        }
+
+        self.diagnostics.push(diag);
+    }
+}
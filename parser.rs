@@ -1,36 +1,99 @@
+use crate::bigint::BigUint;
+use crate::cst::{self, Checkpoint, NodeKind, SyntaxNode};
+use crate::diagnostic::Diagnostic;
 use crate::lexer::{Token, TokenType};
+use crate::lower;
+use std::fmt;
+
+use crate::span::Span;
+use crate::types::IntTy;
 
 #[derive(Debug)]
 pub enum ASTNode {
 
-    Eof,
+    // Placeholder for a statement that failed to parse, spanning the tokens
+    // skipped while recovering.
+    Error(Span),
+
+    // Exact value and optional type suffix (`10u8`).
+    IntLiteral(BigUint, Option<IntTy>, Span),
+
+    FloatLiteral(f64, Span),
 
-    Number(f64),
+    Identifier(String, Span),
 
-    Identifier(String),
+    StrLiteral(String, Span),
 
-    StrLiteral(String),
+    CharLiteral(char, Span),
 
-    BreakNode,
+    BreakNode(Span),
 
-    BoolNode(bool),
+    BoolNode(bool, Span),
 
-    ReturnNode(Option<Box<ASTNode>>),
+    ReturnNode(Option<Box<ASTNode>>, Span),
+
+    UnaryOpNode {
+        op: String,
+        operand: Box<ASTNode>,
+        span: Span,
+    },
 
     BinOpNode {
         op: String,
         left: Box<ASTNode>,
         right: Box<ASTNode>,
+        span: Span,
     },
 
-    VarDecNode {
-        name: String,
+    VarDecNode(VarDecl),
+
+    ConstDecl(ConstDecl),
+
+    // `*ptr`
+    DerefNode {
+        operand: Box<ASTNode>,
+        span: Span,
+    },
+
+    // `&x` or `&mut x`
+    AddrOfNode {
+        mutable: bool,
+        operand: Box<ASTNode>,
+        span: Span,
+    },
+
+    // `value as T`
+    CastNode {
         value: Box<ASTNode>,
+        ty: TypeExpr,
+        span: Span,
+    },
+
+    // `[a, b, c]`
+    ArrayLiteral {
+        elements: Vec<ASTNode>,
+        span: Span,
     },
 
+    // `[value; count]`
+    ArrayRepeat {
+        value: Box<ASTNode>,
+        count: Box<ASTNode>,
+        span: Span,
+    },
+
+    // `base[index]`
+    IndexNode {
+        base: Box<ASTNode>,
+        index: Box<ASTNode>,
+        span: Span,
+    },
+
+    // `target` is a place expression, see `ASTNode::is_place`.
     AssignNode {
-        name: String,
+        target: Box<ASTNode>,
         value: Box<ASTNode>,
+        span: Span,
     },
 
     IfElseNode {
@@ -38,163 +101,547 @@ pub enum ASTNode {
         then_branch: Vec<ASTNode>,
         elif_branch: Vec<(Box<ASTNode>, Vec<ASTNode>)>,
         else_branch: Option<Vec<ASTNode>>,
+        span: Span,
     },
 
     LoopNode {
         condition: Box<ASTNode>,
         block: Vec<ASTNode>,
+        span: Span,
     },
 
     FuncCall {
         name: String,
+        name_span: Span,
         arguments: Vec<ASTNode>,
+        span: Span,
     },
 
-    FuncDef {
-        name: String,
-        arguments: Vec<ASTNode>,
-        block: Vec<ASTNode>,
+    FuncDef(Function),
+
+    TypeDecl(TypeDecl),
+
+}
+
+// `let [mut] name [: T] [= value];`, a local or, at the top level, a global.
+// Without a value the variable is assigned later.
+#[derive(Debug)]
+pub struct VarDecl {
+    pub doc: Option<String>,
+    pub name: String,
+    pub name_span: Span,
+    pub mutable: bool,
+    pub ty: Option<TypeExpr>,
+    pub value: Option<Box<ASTNode>>,
+    pub span: Span,
+}
+
+// `const NAME: T = value;`
+#[derive(Debug)]
+pub struct ConstDecl {
+    pub doc: Option<String>,
+    pub name: String,
+    pub name_span: Span,
+    pub ty: TypeExpr,
+    pub value: Box<ASTNode>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub doc: Option<String>,
+    pub name: String,
+    pub name_span: Span,
+    pub arguments: Vec<Param>,
+    pub ret_type: Option<TypeExpr>,
+    pub block: Vec<ASTNode>,
+    pub span: Span,
+}
+
+// `type Name = T;`
+#[derive(Debug)]
+pub struct TypeDecl {
+    pub doc: Option<String>,
+    pub name: String,
+    pub name_span: Span,
+    pub ty: TypeExpr,
+    pub span: Span,
+}
+
+// Declarations at the top level of a file.
+#[derive(Debug)]
+pub enum Item {
+    Function(Function),
+    Global(VarDecl),
+    Const(ConstDecl),
+    Type(TypeDecl),
+    // A top-level statement that did not parse, spanning the skipped tokens.
+    Error(Span),
+}
+
+impl Item {
+
+    pub fn span(&self) -> Span {
+        match self {
+            Item::Function(func) => func.span,
+            Item::Global(var) => var.span,
+            Item::Const(constant) => constant.span,
+            Item::Type(decl) => decl.span,
+            Item::Error(span) => *span,
+        }
+    }
+}
+
+// A parsed file: its declarations, in source order, and the executable
+// statements found between them.
+#[derive(Debug)]
+pub struct Module {
+    pub items: Vec<Item>,
+    pub top_level_statements: Vec<ASTNode>,
+}
+
+// A type as written in the source. Names other than the builtin integer
+// types and `bool` refer to a `type` declaration.
+#[derive(Debug)]
+pub enum TypeExpr {
+    Int(IntTy, Span),
+    Bool(Span),
+    Named(String, Span),
+    Pointer {
+        mutable: bool,
+        pointee: Box<TypeExpr>,
+        span: Span,
+    },
+    Array {
+        element: Box<TypeExpr>,
+        size: Box<ASTNode>,
+        span: Span,
     },
+}
+
+impl TypeExpr {
 
+    pub fn span(&self) -> Span {
+        match self {
+            TypeExpr::Int(_, span)
+            | TypeExpr::Bool(span)
+            | TypeExpr::Named(_, span)
+            | TypeExpr::Pointer { span, .. }
+            | TypeExpr::Array { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypeExpr::Int(ty, _) => write!(f, "{}", ty),
+            TypeExpr::Bool(_) => write!(f, "bool"),
+            TypeExpr::Named(name, _) => write!(f, "{}", name),
+            TypeExpr::Pointer { mutable, pointee, .. } => {
+                if *mutable {
+                    write!(f, "*mut {}", pointee)
+                } else {
+                    write!(f, "*{}", pointee)
+                }
+            }
+            TypeExpr::Array { element, size, .. } => match size.as_ref() {
+                ASTNode::IntLiteral(n, _, _) => write!(f, "[{}; {}]", element, n),
+                ASTNode::Identifier(name, _) => write!(f, "[{}; {}]", element, name),
+                _ => write!(f, "[{}; _]", element),
+            },
+        }
+    }
 }
 
-pub struct Parser {
-    tokens: Vec<Token>,
+#[derive(Debug)]
+pub struct Param {
+    pub name: String,
+    pub name_span: Span,
+    pub mutable: bool,
+    pub ty: TypeExpr,
+    pub span: Span,
+}
+
+impl ASTNode {
+
+    pub fn span(&self) -> Span {
+        match self {
+            ASTNode::Error(span)
+            | ASTNode::IntLiteral(_, _, span)
+            | ASTNode::FloatLiteral(_, span)
+            | ASTNode::Identifier(_, span)
+            | ASTNode::StrLiteral(_, span)
+            | ASTNode::CharLiteral(_, span)
+            | ASTNode::BreakNode(span)
+            | ASTNode::BoolNode(_, span)
+            | ASTNode::ReturnNode(_, span)
+            | ASTNode::UnaryOpNode { span, .. }
+            | ASTNode::BinOpNode { span, .. }
+            | ASTNode::VarDecNode(VarDecl { span, .. })
+            | ASTNode::ConstDecl(ConstDecl { span, .. })
+            | ASTNode::DerefNode { span, .. }
+            | ASTNode::AddrOfNode { span, .. }
+            | ASTNode::CastNode { span, .. }
+            | ASTNode::ArrayLiteral { span, .. }
+            | ASTNode::ArrayRepeat { span, .. }
+            | ASTNode::IndexNode { span, .. }
+            | ASTNode::AssignNode { span, .. }
+            | ASTNode::IfElseNode { span, .. }
+            | ASTNode::LoopNode { span, .. }
+            | ASTNode::FuncCall { span, .. }
+            | ASTNode::FuncDef(Function { span, .. })
+            | ASTNode::TypeDecl(TypeDecl { span, .. }) => *span,
+        }
+    }
+
+    // Whether the node denotes a memory location that can be assigned to
+    // or have its address taken.
+    pub fn is_place(&self) -> bool {
+        match self {
+            ASTNode::Identifier(..) | ASTNode::DerefNode { .. } => true,
+            ASTNode::IndexNode { base, .. } => base.is_place(),
+            _ => false,
+        }
+    }
+}
+
+pub struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<Token<'a>>,
     pos: usize,
+    diagnostics: Vec<Diagnostic>,
+    cst: cst::Builder<'a>,
+    // Levels of nesting around the current token, see `nest`.
+    nesting: usize,
+}
+
+// Deepest nesting the parser accepts. The later passes walk the tree
+// recursively, so this keeps them within the stack.
+const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    // `a < b < c` is rejected instead of being read as `(a < b) < c`.
+    NonAssoc,
+}
+
+// Every binary operator with its precedence and associativity, loosest
+// binding first. Adding an operator only needs a row here.
+const BINARY_OPERATORS: &[(TokenType, u8, Assoc)] = &[
+    (TokenType::Or, 1, Assoc::Left),
+    (TokenType::And, 2, Assoc::Left),
+    (TokenType::Pipe, 3, Assoc::Left),
+    (TokenType::Caret, 4, Assoc::Left),
+    (TokenType::Amp, 5, Assoc::Left),
+    (TokenType::Eqv, 6, Assoc::NonAssoc),
+    (TokenType::Neq, 6, Assoc::NonAssoc),
+    (TokenType::Les, 7, Assoc::NonAssoc),
+    (TokenType::Gre, 7, Assoc::NonAssoc),
+    (TokenType::Leq, 7, Assoc::NonAssoc),
+    (TokenType::Geq, 7, Assoc::NonAssoc),
+    (TokenType::Shl, 8, Assoc::Left),
+    (TokenType::Shr, 8, Assoc::Left),
+    (TokenType::Add, 9, Assoc::Left),
+    (TokenType::Sub, 9, Assoc::Left),
+    (TokenType::Mul, 10, Assoc::Left),
+    (TokenType::Div, 10, Assoc::Left),
+    (TokenType::Mod, 10, Assoc::Left),
+];
+
+fn binary_operator(ttype: &TokenType) -> Option<(u8, Assoc)> {
+    return BINARY_OPERATORS
+        .iter()
+        .find(|(op, _, _)| op == ttype)
+        .map(|(_, prec, assoc)| (*prec, *assoc));
+}
+
+// Splits an integer literal into its value and type suffix.
+pub fn parse_int_literal(text: &str) -> (BigUint, Option<IntTy>) {
+    let (radix, body) = match text.get(..2) {
+        Some("0x") => (16, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        Some("0o") => (8, &text[2..]),
+        _ => (10, text),
+    };
+
+    let (digits, suffix) = match body.find(['u', 'i']) {
+        Some(index) => (&body[..index], IntTy::from_name(&body[index..])),
+        None => (body, None),
+    };
+
+    return (BigUint::from_digits(digits, radix), suffix);
+}
+
+// Human readable name of a token for "expected X, found Y" messages.
+fn describe(token: &Token) -> String {
+    match token.ttype {
+        TokenType::Eof => "end of file".to_string(),
+        TokenType::Str => "string literal".to_string(),
+        TokenType::Char => "character literal".to_string(),
+        TokenType::Doc => "doc comment".to_string(),
+        _ => format!("`{}`", token.value),
+    }
 }
 
-impl Parser {
+impl<'a> Parser<'a> {
+
+    // `tokens` were lexed from `src`. Whitespace and comments among them,
+    // from `Lexer::with_trivia`, are kept in the syntax tree, otherwise the
+    // text between tokens is kept as `Unknown` tokens.
+    pub fn new(tokens: Vec<Token<'a>>, src: &'a str) -> Self {
+        let (trivia, tokens): (Vec<Token<'a>>, Vec<Token<'a>>) =
+            tokens.into_iter().partition(|token| token.ttype.is_trivia());
+        let file = tokens.last().expect("token stream without end of file").span.file;
 
-    pub fn new(tokens: Vec<Token>) -> Self {
         return Self {
+            src,
             tokens,
             pos: 0,
+            diagnostics: Vec::new(),
+            cst: cst::Builder::new(src, file, trivia),
+            nesting: 0,
         };
     }
 
     fn consume(&mut self) {
-        if self.pos <= self.tokens.len() {
+        if self.pos < self.tokens.len() - 1 {
+            self.cst.token(&self.tokens[self.pos]);
             self.pos += 1;
         }
     }
 
-    fn puke(&mut self) {
-        if self.pos - 1 >= 0 {
-            self.pos-=1;
-        }
+    fn checkpoint(&mut self) -> Checkpoint {
+        let next = self.current().span.start;
+        return self.cst.checkpoint(next);
     }
 
-    fn current(&self) -> Option<&Token> {
-        return self.tokens.get(self.pos);
+    // Closes a syntax tree node around everything consumed since
+    // `checkpoint`.
+    fn wrap(&mut self, checkpoint: Checkpoint, kind: NodeKind) {
+        self.cst.wrap(checkpoint, kind);
+    }
+
+    // The token stream always ends with Eof, so running past the end keeps
+    // returning it instead of failing.
+    fn current(&self) -> &Token<'a> {
+        return &self.tokens[self.pos];
     }    
 
+    fn peek(&self, offset: usize) -> &Token<'a> {
+        let index = (self.pos + offset).min(self.tokens.len() - 1);
+        return &self.tokens[index];
+    }
+
+    // Span of the last consumed token, used to close off node spans.
+    fn prev_span(&self) -> Span {
+        return self.tokens[self.pos.max(1) - 1].span;
+    }
+
     pub fn is_at_end(&self) -> bool {
-        matches!(self.current(), Some(Token { ttype: TokenType::Eof, .. }))
+        matches!(self.current(), Token { ttype: TokenType::Eof, .. })
     }
 
-    fn parse_factor(&mut self) -> Option<ASTNode> {
-        let token = self.current()?; // Safely unwrap Option<&Token>
+    fn unexpected(&self, code: &'static str, expected: &str) -> Diagnostic {
+        let token = self.current();
+        return Diagnostic::error(code, format!("expected {}, found {}", expected, describe(token)), token.span)
+            .with_label(format!("expected {}", expected));
+    }
 
-        match token.ttype {
-            TokenType::Num => {
-                let num = token.value.parse::<f64>().ok()?;
-                self.consume();
-                return Some(ASTNode::Number(num));
-            }
+    // Consumes the current token if it has type `ttype`, otherwise reports
+    // that `expected` was missing.
+    fn expect(&mut self, ttype: TokenType, code: &'static str, expected: &str) -> Result<Span, Diagnostic> {
+        if self.current().ttype != ttype {
+            return Err(self.unexpected(code, expected));
+        }
+        let span = self.current().span;
+        self.consume();
+        return Ok(span);
+    }
+
+    fn expect_semicolon(&mut self) -> Result<Span, Diagnostic> {
+        if self.current().ttype != TokenType::Scln {
+            return Err(self.unexpected("E0103", "`;`")
+                .with_secondary(self.prev_span(), "statement ends here"));
+        }
+        let span = self.current().span;
+        self.consume();
+        return Ok(span);
+    }
+
+
+    // Enters one more level of nesting: a block, a type, a subexpression or
+    // an operator chained onto its left operand, as `1 + 1 + 1` nests to the
+    // left. The caller leaves the level again once it parsed successfully,
+    // after an error `parse_statement_recovering` resets the count.
+    fn nest(&mut self) -> Result<(), Diagnostic> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err(Diagnostic::error("E0115", "too many levels of nesting", self.current().span)
+                .with_label(format!("more than {} levels deep", MAX_NESTING))
+                .with_note("split the code up, for example with `let` bindings or helper functions"));
+        }
+        return Ok(());
+    }
+
+    fn expect_identifier(&mut self, what: &str) -> Result<Span, Diagnostic> {
+        if self.current().ttype != TokenType::Iden {
+            return Err(self.unexpected("E0108", what));
+        }
+        let span = self.current().span;
+        self.consume();
+        return Ok(span);
+    }
+
+    fn parse_factor(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let token = self.current();
 
+        match token.ttype {
             TokenType::Iden => { 
-                self.consume();
-                if self.current()?.ttype == TokenType::Opt {
-                    self.puke();
+                if self.peek(1).ttype == TokenType::Opt {
                     return self.parse_func_call();
                 }
                 else { 
-                    self.puke(); 
-                    let iden = self.current()?.value.clone();
                     self.consume();
-                    return Some(ASTNode::Identifier(iden));
+                    self.wrap(checkpoint, NodeKind::Name);
+                    return Ok(());
                 }
             }
 
-            TokenType::Str => {
-                let iden = token.value.clone();
+            TokenType::Num | TokenType::Str | TokenType::Char | TokenType::True | TokenType::False => {
                 self.consume();
-                return Some(ASTNode::StrLiteral(iden));
+                self.wrap(checkpoint, NodeKind::Literal);
+                return Ok(());
             }
 
-            TokenType::True => {
-                self.consume();
-                return Some(ASTNode::BoolNode(true));
-            }
-
-            TokenType::False => {
-                self.consume();
-                return Some(ASTNode::BoolNode(false));
+            TokenType::Osq => {
+                return self.parse_array();
             }
 
             TokenType::Opt => { 
+                let open = token.span;
                 self.consume();
-                let node = self.parse_expr(false)?; 
-                let next = self.current()?; 
-                if next.ttype != TokenType::Cpt {
-                    println!("[!] Error parsing at Token: {}", self.pos);
-                    println!("{:?}", self.current()?);
-                    return None;
+                self.parse_expr(false)?; 
+                if self.current().ttype != TokenType::Cpt {
+                    return Err(self.unexpected("E0102", "`)`")
+                        .with_secondary(open, "unclosed delimiter"));
                 }
                 self.consume();
-                return Some(node);
+                self.wrap(checkpoint, NodeKind::Paren);
+                return Ok(());
             }
 
             _ => {
-                    println!("[!] Error parsing at Token: {}", self.pos);
-                    println!("{:?}", self.current()?);
-                    return None;
+                    return Err(self.unexpected("E0101", "expression"));
                 }
         }
     }
 
-    fn parse_term(&mut self) -> Option<ASTNode> {
-        let mut node = self.parse_factor()?;
+    // Prefix operators bind tighter than any binary operator and nest to
+    // the right, so `-~x` is `-(~x)` and `-a * b` is `(-a) * b`.
+    fn parse_unary(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let token = self.current();
 
-        while let Some(token) = self.current() {
-            match token.ttype {
-                TokenType::Mul | TokenType::Div => {
-                    let op = token.value.clone();
-                    self.consume();
-                    node = ASTNode::BinOpNode {
-                        op,
-                        left: Box::new(node),
-                        right: Box::new(self.parse_factor()?),
-                    };
-                }
+        match token.ttype {
+            TokenType::Mul => {
+                self.consume(); // consume *
+                self.parse_operand()?;
+                self.wrap(checkpoint, NodeKind::Deref);
+                return Ok(());
+            }
 
-                _ => break,
+            TokenType::Amp => {
+                self.consume(); // consume &
+                self.parse_mutability();
+                self.parse_operand()?;
+                self.wrap(checkpoint, NodeKind::AddrOf);
+                return Ok(());
             }
+
+            TokenType::Sub | TokenType::Not | TokenType::Tilde => {
+                self.consume();
+                self.parse_operand()?;
+                self.wrap(checkpoint, NodeKind::UnaryOp);
+                return Ok(());
+            }
+
+            _ => self.parse_postfix(),
         }
+    }
 
-        return Some(node);
+    // The operand of a prefix operator, one level deeper.
+    fn parse_operand(&mut self) -> Result<(), Diagnostic> {
+        self.nest()?;
+        self.parse_unary()?;
+        self.nesting -= 1;
+        return Ok(());
     }
 
-    fn parse_arith_expr(&mut self) -> Option<ASTNode> {
-        let mut node = self.parse_term()?;
+    // Indexing binds tighter than prefix operators: `*table[i]` is
+    // `*(table[i])`.
+    fn parse_postfix(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let nesting = self.nesting;
+        self.parse_factor()?;
+
+        while self.current().ttype == TokenType::Osq {
+            let open = self.current().span;
+            self.consume(); // consume [
+            self.parse_expr(false)?;
+            if self.current().ttype != TokenType::Csq {
+                return Err(self.unexpected("E0102", "`]`")
+                    .with_secondary(open, "unclosed delimiter"));
+            }
+            self.consume(); // consume ]
+            self.wrap(checkpoint, NodeKind::Index);
+            self.nest()?;
+        }
 
-        while let Some(token) = self.current() {
-            match token.ttype {
-                TokenType::Add | TokenType::Sub => {
-                    let op = token.value.clone();
-                    self.consume();
-                    node = ASTNode::BinOpNode {
-                        op,
-                        left: Box::new(node),
-                        right: Box::new(self.parse_term()?),
-                    };
+        self.nesting = nesting;
+        return Ok(());
+    }
+
+    // `[a, b, c]` with an optional trailing comma, or `[value; count]`.
+    fn parse_array(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let open = self.current().span;
+        self.consume(); // consume [
+
+        if self.current().ttype != TokenType::Csq {
+            self.parse_expr(false)?;
+
+            if self.current().ttype == TokenType::Scln {
+                self.consume(); // consume ;
+                self.parse_expr(false)?;
+                if self.current().ttype != TokenType::Csq {
+                    return Err(self.unexpected("E0102", "`]`")
+                        .with_secondary(open, "unclosed delimiter"));
                 }
+                self.consume(); // consume ]
+                self.wrap(checkpoint, NodeKind::ArrayRepeat);
+                return Ok(());
+            }
 
-                _ => break,
+            while self.current().ttype == TokenType::Com {
+                self.consume(); // consume ,
+                if self.current().ttype == TokenType::Csq {
+                    break;
+                }
+                self.parse_expr(false)?;
             }
         }
 
-        return Some(node);
+        if self.current().ttype != TokenType::Csq {
+            return Err(self.unexpected("E0102", "`,` or `]`")
+                .with_secondary(open, "unclosed delimiter"));
+        }
+        self.consume(); // consume ]
+        self.wrap(checkpoint, NodeKind::ArrayLiteral);
+
+        return Ok(());
     }
 
 /* Unnecessary
@@ -239,311 +686,492 @@ impl Parser {
     }
 */
 
-    fn parse_comp_expr(&mut self) -> Option<ASTNode> {
-        let mut node = self.parse_arith_expr()?;
+    // `as` binds tighter than any binary operator but looser than prefix
+    // operators, and chains to the left: `-x as u8 as u32` is
+    // `((-x) as u8) as u32`.
+    fn parse_cast(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let nesting = self.nesting;
+        self.parse_unary()?;
+
+        while self.current().ttype == TokenType::As {
+            self.consume(); // consume as
+            self.parse_type()?;
+            self.wrap(checkpoint, NodeKind::Cast);
+            self.nest()?;
+        }
 
-        while let Some(token) = self.current() {
-            match token.ttype {
-                TokenType::Geq | TokenType::Leq | TokenType::Gre | TokenType::Les | TokenType::Eqv => {
-                    let op = token.value.clone();
-                    self.consume();
-                    node = ASTNode::BinOpNode {
-                        op,
-                        left: Box::new(node),
-                        right: Box::new(self.parse_arith_expr()?),
-                    };
-                }
+        self.nesting = nesting;
+        return Ok(());
+    }
 
-                _ => break,
+    // Precedence climbing over BINARY_OPERATORS. Operands are cast
+    // expressions, and only operators binding at least as tightly as
+    // `min_prec` are consumed at this level.
+    fn parse_binary(&mut self, min_prec: u8) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let nesting = self.nesting;
+        self.parse_cast()?;
+        let mut last_non_assoc: Option<(u8, Span)> = None;
+
+        while let Some((prec, assoc)) = binary_operator(&self.current().ttype) {
+            if prec < min_prec {
+                break;
             }
+
+            let op_span = self.current().span;
+
+            if let Some((last_prec, last_span)) = last_non_assoc {
+                if assoc == Assoc::NonAssoc && last_prec == prec {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0111", "comparison operators cannot be chained", op_span)
+                            .with_label("second comparison")
+                            .with_secondary(last_span, "first comparison")
+                            .with_note("split the comparison with `and`, e.g. `a < b and b < c`"),
+                    );
+                }
+            }
+
+            self.consume();
+            self.parse_binary(prec + 1)?;
+            self.wrap(checkpoint, NodeKind::BinOp);
+            self.nest()?;
+
+            last_non_assoc = if assoc == Assoc::NonAssoc { Some((prec, op_span)) } else { None };
         }
 
-        return Some(node);
+        self.nesting = nesting;
+        return Ok(());
     }
 
-    fn parse_logic_expr(&mut self) -> Option<ASTNode> {
-        let mut node = self.parse_comp_expr()?;
+    fn parse_expr(&mut self, terminate: bool) -> Result<(), Diagnostic> {
+        self.nest()?;
+        self.parse_binary(0)?;
+        self.nesting -= 1;
+        if terminate {
+            self.expect_semicolon()?;
+        }
 
-        while let Some(token) = self.current() {
-            match token.ttype {
-                TokenType::And | TokenType::Or => {
-                    let op = token.value.clone();
-                    self.consume();
-                    node = ASTNode::BinOpNode {
-                        op,
-                        left: Box::new(node),
-                        right: Box::new(self.parse_comp_expr()?),
-                    };
-                }
+        return Ok(());
+    }
 
-                _ => break,
+    // Parses the whole file into its syntax tree, then derives the AST from
+    // the tree. Syntax errors do not stop the parse: each one is recorded,
+    // the offending statement becomes an `Error` node and parsing resumes
+    // at the next statement. Statements that failed to parse are left out
+    // of the module.
+    pub fn parse_program(mut self) -> (SyntaxNode, Module, Vec<Diagnostic>) {
+        while !self.is_at_end() {
+            if self.skip_dangling_docs() {
+                continue;
             }
+            self.parse_statement_recovering();
         }
 
-        return Some(node);
+        let file = self.current().span.file;
+        let tree = self.cst.finish();
+        let (module, diagnostics) = lower::lower(&tree, self.src, file);
+
+        // Lowering runs after parsing, so its errors are slotted back in
+        // among the syntax errors in source order.
+        for diag in diagnostics {
+            let start = diag.primary.span.start;
+            let index = self.diagnostics.iter()
+                .position(|other| other.primary.span.start > start)
+                .unwrap_or(self.diagnostics.len());
+            self.diagnostics.insert(index, diag);
+        }
+
+        return (tree, module, self.diagnostics);
     }
 
-    fn parse_expr(&mut self, terminate: bool) -> Option<ASTNode> {
-        match self.current()?.ttype {
-            
-            TokenType::Iden | TokenType::Num | TokenType::Str | 
-            TokenType::True | TokenType::False => {
-                let mut node = self.parse_logic_expr()?;
-                if terminate == true && self.current()?.ttype != TokenType::Scln {
-                    println!("Expected semicolon!!!");
-                    return None;
-                } else if terminate == true && self.current()?.ttype == TokenType::Scln {
+    fn parse_statement_recovering(&mut self) {
+        let checkpoint = self.checkpoint();
+        let start_pos = self.pos;
+        let nesting = self.nesting;
+
+        if let Err(diag) = self.parse_statement() {
+            self.nesting = nesting;
+            self.diagnostics.push(diag);
+            self.synchronize(start_pos);
+            self.wrap(checkpoint, NodeKind::Error);
+        }
+    }
+
+    // Skips tokens until a point where a new statement can start: after a
+    // `;`, after the `}` closing a block that was entered while skipping,
+    // before a `}` that closes an enclosing block, or before `fn`, `let`,
+    // `const`, `if`, `loop`, `type` or a doc comment. If the failed
+    // statement starting at `start_pos` did not consume anything, at least
+    // one token is skipped so the parser cannot stall.
+    fn synchronize(&mut self, start_pos: usize) {
+        let mut depth = 0;
+        let mut skipped = self.pos > start_pos;
+
+        loop {
+            let ttype = &self.current().ttype;
+
+            match ttype {
+                TokenType::Eof => return,
+
+                TokenType::Scln if depth == 0 => {
                     self.consume();
+                    return;
+                }
+
+                TokenType::Ocl => depth += 1,
+
+                TokenType::Ccl => {
+                    if depth == 0 {
+                        if skipped {
+                            return;
+                        }
+                    } else {
+                        depth -= 1;
+                        if depth == 0 {
+                            self.consume();
+                            return;
+                        }
+                    }
                 }
 
-                return Some(node);
+                TokenType::Func | TokenType::Let | TokenType::Const | TokenType::If | TokenType::Loop
+                | TokenType::Type | TokenType::Doc
+                    if depth == 0 && skipped => return,
+
+                _ => {}
             }
 
-            _ =>    {
-                        None
-                    },
+            self.consume();
+            skipped = true;
         }
-
     }
 
-    pub fn parse_statement(&mut self) -> Option<ASTNode> {
-        match self.current()?.ttype {
-            TokenType::Eof => return Some(ASTNode::Eof),
-            TokenType::Let => self.parse_var_def(),
-            TokenType::Func => self.parse_func_def(),
-            TokenType::If => self.parse_ifelse(),
-            TokenType::Loop => self.parse_loop(),
 
-            TokenType::Break => {
-                                    self.consume(); // consume break
-                                    if self.current()?.ttype != TokenType::Scln {
-                                        println!("Expected semicolon near break!");
-                                        return None;
-                                    }
-                                    self.consume();
-                                    Some(ASTNode::BreakNode)
-                                }
+    // Consumes the `///` lines directly before a statement and returns
+    // their span. The text is read back from the syntax tree.
+    fn parse_doc_comments(&mut self) -> Option<Span> {
+        if self.current().ttype != TokenType::Doc {
+            return None;
+        }
 
-            TokenType::Return => {
-                self.consume(); // consume return
-                if let Some(node) = self.parse_expr(true) {
+        let start = self.current().span;
+        while self.current().ttype == TokenType::Doc {
+            self.consume();
+        }
 
-                    Some(ASTNode::ReturnNode(Some(Box::new(node))))
+        return Some(start.to(self.prev_span()));
+    }
 
-                } else {
+    fn unattached_doc(&mut self, span: Span) {
+        self.diagnostics.push(
+            Diagnostic::warning("W0101", "doc comment is not attached to a declaration", span)
+                .with_label("this doc comment is ignored")
+                .with_note("doc comments document the `fn`, `let`, `const` or `type` that follows them"));
+    }
 
-                    if self.current()?.ttype != TokenType::Scln {
-                        println!("Expected semicolon near break!");
-                        return None;
-                    }
+    // Consumes doc comments that end a block or the file, where there is no
+    // statement left to attach them to. Returns whether any were skipped.
+    fn skip_dangling_docs(&mut self) -> bool {
+        let mut offset = 0;
+        while self.peek(offset).ttype == TokenType::Doc {
+            offset += 1;
+        }
+        if offset == 0 || !matches!(self.peek(offset).ttype, TokenType::Ccl | TokenType::Eof) {
+            return false;
+        }
 
-                    self.consume();
-                    Some(ASTNode::ReturnNode(None))
-                }
+        if let Some(span) = self.parse_doc_comments() {
+            self.unattached_doc(span);
+        }
+        return true;
+    }
+
+    fn parse_statement(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+
+        if let Some(span) = self.parse_doc_comments() {
+            if !matches!(self.current().ttype, TokenType::Let | TokenType::Const | TokenType::Func | TokenType::Type) {
+                self.unattached_doc(span);
             }
+        }
 
-            TokenType::Iden => {
+        let kind = match self.current().ttype {
+            TokenType::Eof => return Ok(()),
+            TokenType::Let => self.parse_var_def().map(|_| NodeKind::VarDec),
+            TokenType::Const => self.parse_const_def().map(|_| NodeKind::ConstDecl),
+            TokenType::Func => self.parse_func_def().map(|_| NodeKind::FuncDef),
+            TokenType::Type => self.parse_type_decl().map(|_| NodeKind::TypeDecl),
+            TokenType::If => self.parse_ifelse().map(|_| NodeKind::If),
+            TokenType::Loop => self.parse_loop().map(|_| NodeKind::Loop),
 
-                self.consume();
-                if self.current()?.ttype == TokenType::Opt {
-                    self.puke();
-                    let node = self.parse_func_call();
+            TokenType::Break => {
+                                    self.consume(); // consume break
+                                    self.expect_semicolon()?;
+                                    Ok(NodeKind::Break)
+                                }
 
-                    if self.current()?.ttype != TokenType::Scln {
-                        println!("Expected semicolon!!!");
-                        return None;
-                    }
+            TokenType::Return => {
+                self.consume(); // consume return
+                if self.current().ttype == TokenType::Scln {
                     self.consume();
-                    return node;
-                }
-                else if self.current()?.ttype == TokenType::Equ {
-                    self.puke();
-                    return self.parse_assign();
-                }
-                else {  
-                    println!("[!]Error parsing at Token (Statement) : {}", self.pos);
-                    println!("{:?}", self.current()?);
-                    return None; 
+                } else {
+                    self.parse_expr(true)?;
                 }
+                Ok(NodeKind::Return)
             }
 
-            _ => self.parse_expr(true),
-        }
+            _ => self.parse_expr_statement(),
+        }?;
+
+        self.wrap(checkpoint, kind);
+
+        return Ok(());
     }
 
-    fn parse_block(&mut self) -> Option<Vec<ASTNode>> {
-        self.consume(); // consume {
-        
-        let mut statements: Vec<ASTNode> = Vec::new();
+    fn parse_block(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let open = self.expect(TokenType::Ocl, "E0105", "`{`")?;
+        self.nest()?;
 
-        while let Some(token) = self.current() {
+        loop {
+            if self.skip_dangling_docs() {
+                continue;
+            }
+
+            let token = self.current();
 
             if token.ttype == TokenType::Ccl {
                 break
             }
 
-            if let Some(node) = self.parse_statement() {
-                statements.push(node);
-            } else {
-                return None;
+            if token.ttype == TokenType::Eof {
+                return Err(self.unexpected("E0105", "`}`")
+                    .with_secondary(open, "block starts here")); // unterminated block
             }
-        } 
 
-        if self.current()?.ttype != TokenType::Ccl {
-            println!("[!] Error parsing at Token: (Block Error) {}", self.pos);
-            println!("{:?}", self.current()?);
-            return None; // unterminated block
-        }
+            self.parse_statement_recovering();
+        } 
 
         self.consume(); // Consume }
+        self.wrap(checkpoint, NodeKind::Block);
+        self.nesting -= 1;
 
-        return Some(statements);
+        return Ok(());
     }
 
-    fn parse_var_def(&mut self) -> Option<ASTNode> {
-        self.consume(); // consume the 'let'
-        let name = self.current()?.value.clone();
-        self.consume();
-        self.consume(); // consume the '='
-        let value = self.parse_expr(true)?;
+    // Types are `u8`..`u64`, `i8`..`i64`, `bool`, names declared with
+    // `type`, `*T`, `*mut T` and `[T; N]`.
+    fn parse_type(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        self.nest()?;
+        self.parse_type_expr()?;
+        self.wrap(checkpoint, NodeKind::Type);
+        self.nesting -= 1;
+        return Ok(());
+    }
 
-        let mut node = ASTNode::VarDecNode {
-            name: name,
-            value: Box::new(value),
-        };
+    fn parse_type_expr(&mut self) -> Result<(), Diagnostic> {
+        let token = self.current();
+        let start = token.span;
 
-        return Some(node);
-    }
+        match token.ttype {
+            TokenType::Iden => {
+                self.consume();
+                return Ok(());
+            }
 
-    fn parse_func_def(&mut self) -> Option<ASTNode> { 
-        self.consume(); // consume the 'fn'
-        let name = self.current()?.value.clone();
-        self.consume();
+            TokenType::Mul => {
+                self.consume(); // consume *
+                self.parse_mutability();
+                return self.parse_type();
+            }
 
-        let arguments = self.parse_args_def()?;  
+            TokenType::Osq => {
+                self.consume(); // consume [
+                self.parse_type()?;
+                self.expect(TokenType::Scln, "E0112", "`;`")?;
+                self.parse_expr(false)?;
+                if self.current().ttype != TokenType::Csq {
+                    return Err(self.unexpected("E0102", "`]`")
+                        .with_secondary(start, "unclosed delimiter"));
+                }
+                self.consume(); // consume ]
+                return Ok(());
+            }
 
-        if let Some(block) = self.parse_block() {  
+            _ => Err(self.unexpected("E0112", "type")),
+        }
+    }
 
-            let node = ASTNode::FuncDef {
-                name,
-                arguments,
-                block,
-            };
+    // Parses an optional `mut` qualifier.
+    fn parse_mutability(&mut self) -> bool {
+        if self.current().ttype == TokenType::Mut {
+            self.consume();
+            return true;
+        }
+        return false;
+    }
 
-            return Some(node);
+    fn parse_var_def(&mut self) -> Result<(), Diagnostic> {
+        self.consume(); // consume the 'let'
+        self.parse_mutability();
+        self.expect_identifier("variable name")?;
 
+        if self.current().ttype == TokenType::Cln {
+            self.consume(); // consume :
+            self.parse_type()?;
         }
-        else {
-            return None;
+
+        if self.current().ttype == TokenType::Scln {
+            self.consume(); // consume ;
+        } else {
+            self.expect(TokenType::Equ, "E0106", "`=` or `;`")?;
+            self.parse_expr(true)?;
         }
+
+        return Ok(());
     }
 
-    fn parse_func_call(&mut self) -> Option<ASTNode> {
-        let name = self.current()?.value.clone();
-        self.consume();
+    // Constants always spell out their type.
+    fn parse_const_def(&mut self) -> Result<(), Diagnostic> {
+        self.consume(); // consume the 'const'
+        self.expect_identifier("constant name")?;
+        self.expect(TokenType::Cln, "E0114", "`:` and the constant's type")?;
+        self.parse_type()?;
+        self.expect(TokenType::Equ, "E0106", "`=`")?;
+        self.parse_expr(true)?;
 
-        let arguments = self.parse_args_call()?;
+        return Ok(());
+    }
 
-        let node = ASTNode::FuncCall {
-            name,
-            arguments,
-        };
+    fn parse_type_decl(&mut self) -> Result<(), Diagnostic> {
+        self.consume(); // consume the 'type'
+        self.expect_identifier("type name")?;
+        self.expect(TokenType::Equ, "E0106", "`=`")?;
+        self.parse_type()?;
+        self.expect_semicolon()?;
 
-        return Some(node); 
+        return Ok(());
     }
 
-    fn parse_args_def(&mut self) -> Option<Vec<ASTNode>> {
-        self.consume(); // consume (
+    fn parse_func_def(&mut self) -> Result<(), Diagnostic> { 
+        self.consume(); // consume the 'fn'
+        self.expect_identifier("function name")?;
+
+        self.parse_args_def()?;  
+
+        if self.current().ttype == TokenType::Arrow {
+            self.consume(); // consume ->
+            self.parse_type()?;
+        }
+
+        return self.parse_block();
+    }
+
+    fn parse_func_call(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        self.consume();
 
-        let mut arguments = Vec::new();
+        self.parse_args_call()?;
+        self.wrap(checkpoint, NodeKind::Call);
 
-        while let Some(token) = self.current() {
+        return Ok(()); 
+    }
+
+    // Parameters are `[mut] name: T`.
+    fn parse_args_def(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let open = self.expect(TokenType::Opt, "E0107", "`(`")?;
 
-            if token.ttype == TokenType::Cpt {
+        // A trailing comma is allowed, as in calls.
+        loop {
+            if self.current().ttype == TokenType::Cpt {
                 self.consume(); // consume )
-                return Some(arguments);
+                self.wrap(checkpoint, NodeKind::ParamList);
+                return Ok(());
             }
 
-            arguments.push(ASTNode::Identifier(token.value.clone()));
-            self.consume(); // consume identifier
+            let param = self.checkpoint();
+            self.parse_mutability();
+            self.expect_identifier("parameter name")?;
+            self.expect(TokenType::Cln, "E0112", "`:` and a parameter type")?;
+            self.parse_type()?;
+            self.wrap(param, NodeKind::Param);
 
-            match self.current()?.ttype {
+            match self.current().ttype {
 
                 TokenType::Com => {
                     self.consume(); // consume ,
                 }
                 TokenType::Cpt => {
                     self.consume(); // consume )
-                    return Some(arguments);
+                    self.wrap(checkpoint, NodeKind::ParamList);
+                    return Ok(());
                 }
 
                 _ => {
-                        return None;
+                        return Err(self.unexpected("E0107", "`,` or `)`")
+                            .with_secondary(open, "parameter list starts here"));
                      }
             }
         }
-        None
     }
 
-    fn parse_args_call(&mut self) -> Option<Vec<ASTNode>> {
+    fn parse_args_call(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let open = self.current().span;
         self.consume(); // consume (
 
-        let mut arguments = Vec::new();
-
         loop {
-            if self.current()?.ttype == TokenType::Cpt {
+            if self.current().ttype == TokenType::Cpt {
                 self.consume(); // consume )
-                return Some(arguments);
+                self.wrap(checkpoint, NodeKind::ArgList);
+                return Ok(());
             }
 
-            let node = self.parse_expr(false)?; 
-            arguments.push(node);
+            self.parse_expr(false)?; 
 
-            match self.current()?.ttype {
+            match self.current().ttype {
                 TokenType::Com => {
                     self.consume(); // consume ,
                 }
                 TokenType::Cpt => {
                     self.consume(); // consume )
-                    return Some(arguments);
+                    self.wrap(checkpoint, NodeKind::ArgList);
+                    return Ok(());
                 }
                 _ => {
-                        println!("[!] Error parsing at Token: (Call error) {}", self.pos);
-                        println!("{:?}", self.current()?);
-                        return None;
+                        return Err(self.unexpected("E0107", "`,` or `)`")
+                            .with_secondary(open, "argument list starts here"));
                      }
             }
         }
 
     }
 
-    fn parse_loop(&mut self) -> Option<ASTNode> {
-        self.consume(); // consume loop identifier
-
-        if self.current()?.ttype != TokenType::Opt {
-            println!("[!] Error parsing at Token: {}", self.pos);
-            println!("{:?}", self.current()?);
-            return None;
-        }
-        self.consume(); // consume (
+    // Parses `( condition )` after `loop`, `if` and `elif`.
+    fn parse_condition(&mut self) -> Result<(), Diagnostic> {
+        let checkpoint = self.checkpoint();
+        let open = self.expect(TokenType::Opt, "E0107", "`(`")?;
 
-        let condition = self.parse_expr(false)?;
+        self.parse_expr(false)?;
 
-        if self.current()?.ttype != TokenType::Cpt {
-            println!("[!] Error parsing at Token: {}", self.pos);
-            println!("{:?}", self.current()?);
-            return None;
+        if self.current().ttype != TokenType::Cpt {
+            return Err(self.unexpected("E0102", "`)`")
+                .with_secondary(open, "unclosed delimiter"));
         }
         self.consume(); // consume )
+        self.wrap(checkpoint, NodeKind::Condition);
 
-        let block = self.parse_block()?;
+        return Ok(());
+    }
 
-        let node = ASTNode::LoopNode {
-            condition: Box::new(condition),
-            block,
-        };
+    fn parse_loop(&mut self) -> Result<(), Diagnostic> {
+        self.consume(); // consume loop identifier
 
-        return Some(node);
+        self.parse_condition()?;
+        return self.parse_block();
 
     }
 
@@ -558,75 +1186,125 @@ impl Parser {
     
     */
 
-    fn parse_ifelse(&mut self) -> Option<ASTNode> {
+    fn parse_ifelse(&mut self) -> Result<(), Diagnostic> {
         self.consume(); // consume if identifier
 
-        if self.current()?.ttype != TokenType::Opt {
-            return None;
+        self.parse_condition()?;
+        self.parse_block()?;
+
+        while self.current().ttype == TokenType::Elif {
+            let checkpoint = self.checkpoint();
+
+            self.consume(); // consume elif identifier
+
+            self.parse_condition()?;
+            self.parse_block()?;
+            self.wrap(checkpoint, NodeKind::ElifClause);
         }
-        self.consume(); // consume (
 
-        let ifcondition = self.parse_expr(false)?;
-        if self.current()?.ttype != TokenType::Cpt {
-            return None;
+        if self.current().ttype == TokenType::Else {
+            let checkpoint = self.checkpoint();
+            self.consume();
+            self.parse_block()?;
+            self.wrap(checkpoint, NodeKind::ElseClause);
         }
-        self.consume(); // consume )
 
-        let then_branch = self.parse_block()?;
+        return Ok(());
 
-        let mut elif_branches: Vec<(Box<ASTNode>, Vec<ASTNode>)> = Vec::new();
-        while self.current()?.ttype == TokenType::Elif {
+    }
 
-            self.consume(); // consume elif identifier
+    // An expression followed by `;`, or an assignment `place = value;`.
+    fn parse_expr_statement(&mut self) -> Result<NodeKind, Diagnostic> {
+        self.parse_expr(false)?;
 
-            if self.current()?.ttype != TokenType::Opt {
-                return None;
-            }
-            self.consume(); // consume (
+        if self.current().ttype != TokenType::Equ {
+            self.expect_semicolon()?;
+            return Ok(NodeKind::ExprStmt);
+        }
 
-            let elifcondition = self.parse_expr(false)?;
-            if self.current()?.ttype != TokenType::Cpt {
-                return None;
-            }
-            self.consume(); // consume )
+        self.consume(); // consume =
+        self.parse_expr(true)?;
 
-            let ethen_branch = self.parse_block()?;
+        return Ok(NodeKind::Assign);
+    }
 
-            elif_branches.push((Box::new(elifcondition), ethen_branch));
-        }
+}
 
-        let else_branch = if self.current()?.ttype == TokenType::Else {
-            
-                self.consume();
-                Some(self.parse_block()?)
-            }
-            else {
-                None
-            };
+#[cfg(test)]
+mod tests {
+    use super::{ASTNode, Item, Module, Parser};
+    use crate::diagnostic::Diagnostic;
+    use crate::lexer::Lexer;
+
+    fn parse(src: &str) -> (Module, Vec<Diagnostic>) {
+        let tokens = Lexer::new(src, 0).filter_map(Result::ok).collect();
+        let (_, module, diagnostics) = Parser::new(tokens, src).parse_program();
+        return (module, diagnostics);
+    }
+
+    fn codes(diagnostics: &[Diagnostic]) -> Vec<&'static str> {
+        return diagnostics.iter().map(|diag| diag.code).collect();
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let (_, diagnostics) = parse("let a = ;\nfn f() {\n    let b = 1 let c = 2;\n}\nconst E: u8 = 1 +;\n");
+        assert_eq!(codes(&diagnostics), ["E0101", "E0103", "E0101"]);
+    }
 
+    #[test]
+    fn keeps_the_statements_after_an_error() {
+        let (module, diagnostics) = parse("let a = ;\nfn f() {\n    let b = 1 let c = 2;\n    c = 3;\n}\nlet d: u8 = 3;\n");
+        assert_eq!(codes(&diagnostics), ["E0101", "E0103"]);
 
-        return Some(ASTNode::IfElseNode {
-            condition: Box::new(ifcondition),
-            then_branch,
-            elif_branch: elif_branches,
-            else_branch,
+        let function = module.items.iter().find_map(|item| match item {
+            Item::Function(function) => Some(function),
+            _ => None,
         });
+        let block = &function.expect("function lost to recovery").block;
+        assert!(matches!(block[..], [ASTNode::Error(_), ASTNode::VarDecNode(_), ASTNode::AssignNode { .. }]));
 
+        let global = module.items.iter().any(|item| matches!(item, Item::Global(var) if var.name == "d"));
+        assert!(global, "global after the function lost to recovery");
     }
 
-    fn parse_assign(&mut self) -> Option<ASTNode> {
-        let name = self.current()?.value.clone();
-        self.consume();
-        self.consume(); // consume =
+    #[test]
+    fn keeps_top_level_errors_as_items() {
+        let (module, _) = parse("let a = ;\nlet b: u8 = 1;\nconst E: u8 = 1 +;\n");
+        assert!(matches!(module.items[..], [Item::Error(_), Item::Global(_), Item::Error(_)]));
+        assert_eq!(module.items[2].span().line, 3);
+    }
 
-        let value = self.parse_expr(true)?;
+    // Whether a literal fits its type is for the type checker to say, the
+    // syntax is fine.
+    #[test]
+    fn accepts_out_of_range_literals() {
+        let (_, diagnostics) = parse("let a = 300u8;\nlet b = 18446744073709551616;\nlet c = -129i8;\n");
+        assert!(diagnostics.is_empty());
+    }
 
-        let node = ASTNode::AssignNode {
-            name,
-            value: Box::new(value),
+    // Runs on a thread with the main thread's usual 8 MiB of stack, which
+    // the compiler gets, rather than the smaller stack of a test thread.
+    #[test]
+    fn rejects_deep_nesting() {
+        let check = || {
+            let chain = vec!["1"; 4000].join(" + ");
+            let (_, diagnostics) = parse(&format!("fn f() -> u32 {{ return {}; }}\n", chain));
+            assert_eq!(codes(&diagnostics), ["E0115"]);
+
+            let parens = format!("{}1{}", "(".repeat(4000), ")".repeat(4000));
+            let (_, diagnostics) = parse(&format!("let a: u32 = {};\nlet b: u32 = {};\n", parens, chain));
+            assert_eq!(codes(&diagnostics), ["E0115", "E0115"]);
         };
 
-        return Some(node);
+        let thread = std::thread::Builder::new().stack_size(8 << 20).spawn(check).unwrap();
+        thread.join().unwrap();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn recovers_from_an_unclosed_block() {
+        let (module, diagnostics) = parse("fn f() {\n    if (true { }\n}\nfn g() { }\n");
+        assert!(!diagnostics.is_empty());
+        assert!(module.items.iter().any(|item| matches!(item, Item::Function(function) if function.name == "g")));
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, Function, Item, Module, TypeExpr};
+use crate::span::Span;
+
+// Binds every name in a module to its declaration. Values (globals,
+// constants, functions, parameters and locals) and types live in separate
+// namespaces. Items are visible in the whole file, locals from their `let`
+// to the end of the enclosing block. A function nested in a block sees the
+// items around it but not the locals of the function it is nested in.
+// A name can be declared once per scope, shadowing needs an inner block.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeclId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeclKind {
+    Global,
+    Const,
+    Function,
+    Param,
+    Local,
+    Type,
+}
+
+impl DeclKind {
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            DeclKind::Global => "global",
+            DeclKind::Const => "constant",
+            DeclKind::Function => "function",
+            DeclKind::Param => "parameter",
+            DeclKind::Local => "local variable",
+            DeclKind::Type => "type",
+        }
+    }
+
+    fn is_item(self) -> bool {
+        !matches!(self, DeclKind::Param | DeclKind::Local)
+    }
+}
+
+#[derive(Debug)]
+pub struct Decl {
+    pub name: String,
+    pub kind: DeclKind,
+    pub mutable: bool,
+    // Span of the declared name.
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Resolution {
+    pub decls: Vec<Decl>,
+    // What each use refers to, keyed by the span of the name as written:
+    // identifiers, the names of called functions and named types.
+    pub uses: HashMap<Span, DeclId>,
+    // Each declaration, keyed by the span of the declared name.
+    pub declarations: HashMap<Span, DeclId>,
+}
+
+impl Resolution {
+
+    pub fn decl(&self, id: DeclId) -> &Decl {
+        return &self.decls[id.0];
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Namespace {
+    Value,
+    Type,
+}
+
+struct Scope {
+    values: HashMap<String, DeclId>,
+    types: HashMap<String, DeclId>,
+    // Set on the scope holding a function's parameters. Locals further out
+    // belong to another function.
+    function: bool,
+}
+
+impl Scope {
+    fn names(&self, namespace: Namespace) -> &HashMap<String, DeclId> {
+        match namespace {
+            Namespace::Value => &self.values,
+            Namespace::Type => &self.types,
+        }
+    }
+}
+
+struct Resolver {
+    resolution: Resolution,
+    scopes: Vec<Scope>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn resolve(module: &Module) -> (Resolution, Vec<Diagnostic>) {
+    let mut resolver = Resolver {
+        resolution: Resolution {
+            decls: Vec::new(),
+            uses: HashMap::new(),
+            declarations: HashMap::new(),
+        },
+        scopes: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    resolver.push_scope(false);
+
+    // Items are declared up front so they can be used before their
+    // declaration.
+    for item in &module.items {
+        match item {
+            Item::Function(func) => resolver.declare(Namespace::Value, &func.name, DeclKind::Function, false, func.name_span),
+            Item::Global(var) => resolver.declare(Namespace::Value, &var.name, DeclKind::Global, var.mutable, var.name_span),
+            Item::Const(constant) => resolver.declare(Namespace::Value, &constant.name, DeclKind::Const, false, constant.name_span),
+            Item::Type(decl) => resolver.declare(Namespace::Type, &decl.name, DeclKind::Type, false, decl.name_span),
+            Item::Error(_) => {}
+        }
+    }
+
+    for item in &module.items {
+        match item {
+            Item::Function(func) => resolver.resolve_function(func),
+            Item::Global(var) => {
+                if let Some(ty) = &var.ty {
+                    resolver.resolve_type(ty);
+                }
+                if let Some(value) = &var.value {
+                    resolver.resolve_expr(value);
+                }
+            }
+            Item::Const(constant) => {
+                resolver.resolve_type(&constant.ty);
+                resolver.resolve_expr(&constant.value);
+            }
+            Item::Type(decl) => resolver.resolve_type(&decl.ty),
+            Item::Error(_) => {}
+        }
+    }
+
+    return (resolver.resolution, resolver.diagnostics);
+}
+
+impl Resolver {
+
+    fn push_scope(&mut self, function: bool) {
+        self.scopes.push(Scope {
+            values: HashMap::new(),
+            types: HashMap::new(),
+            function,
+        });
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, namespace: Namespace, name: &str, kind: DeclKind, mutable: bool, span: Span) {
+        let id = DeclId(self.resolution.decls.len());
+        self.resolution.decls.push(Decl {
+            name: name.to_string(),
+            kind,
+            mutable,
+            span,
+        });
+        self.resolution.declarations.insert(span, id);
+
+        let scope = self.scopes.last_mut().unwrap();
+        let names = match namespace {
+            Namespace::Value => &mut scope.values,
+            Namespace::Type => &mut scope.types,
+        };
+
+        // Later uses bind to the newest declaration either way.
+        if let Some(previous) = names.insert(name.to_string(), id) {
+            let previous = &self.resolution.decls[previous.0];
+            let mut diag = Diagnostic::error("E0302", format!("the name `{}` is defined multiple times", name), span)
+                .with_label(format!("`{}` redefined here", name))
+                .with_secondary(previous.span, format!("previous definition of the {} `{}` here", previous.kind.describe(), name));
+            if !kind.is_item() && !previous.kind.is_item() {
+                diag = diag.with_note("a name can only be shadowed in an inner block");
+            }
+            self.diagnostics.push(diag);
+        }
+    }
+
+    // Records what `name`, written at `span`, refers to. `what` names the
+    // expected kind of thing in the error if nothing is found.
+    fn lookup(&mut self, namespace: Namespace, name: &str, span: Span, what: &str) {
+        let mut outside_function = false;
+
+        for scope in self.scopes.iter().rev() {
+            if let Some(&id) = scope.names(namespace).get(name) {
+                let decl = &self.resolution.decls[id.0];
+                if outside_function && !decl.kind.is_item() {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0303", format!("cannot use `{}` from the enclosing function", name), span)
+                            .with_label("used in a nested function")
+                            .with_secondary(decl.span, format!("`{}` is a {} of the enclosing function", name, decl.kind.describe()))
+                            .with_note("nested functions only see items, not the locals of the function around them"),
+                    );
+                    return;
+                }
+                self.resolution.uses.insert(span, id);
+                return;
+            }
+            if scope.function {
+                outside_function = true;
+            }
+        }
+
+        let diag = Diagnostic::error("E0301", format!("cannot find {} `{}` in this scope", what, name), span);
+
+        let diag = match self.suggest(namespace, name) {
+            Some(id) => {
+                let decl = &self.resolution.decls[id.0];
+                diag.with_label(format!("did you mean `{}`?", decl.name))
+                    .with_secondary(decl.span, format!("similarly named {} `{}` defined here", decl.kind.describe(), decl.name))
+            }
+            None => diag.with_label("not found in this scope"),
+        };
+
+        self.diagnostics.push(diag);
+    }
+
+    // The visible name closest to `name`, if any is close enough to be a
+    // likely typo.
+    fn suggest(&self, namespace: Namespace, name: &str) -> Option<DeclId> {
+        let limit = (name.chars().count() / 3).max(1);
+        let mut best: Option<(usize, DeclId)> = None;
+        let mut outside_function = false;
+
+        for scope in self.scopes.iter().rev() {
+            for (candidate, &id) in scope.names(namespace) {
+                if outside_function && !self.resolution.decls[id.0].kind.is_item() {
+                    continue;
+                }
+                let distance = edit_distance(name, candidate);
+                // Ties go to the earliest declaration, to not depend on
+                // hash map order.
+                if distance <= limit && best.is_none_or(|(best, best_id)| (distance, id.0) < (best, best_id.0)) {
+                    best = Some((distance, id));
+                }
+            }
+            if scope.function {
+                outside_function = true;
+            }
+        }
+
+        return best.map(|(_, id)| id);
+    }
+
+    fn resolve_function(&mut self, func: &Function) {
+        if let Some(ty) = &func.ret_type {
+            self.resolve_type(ty);
+        }
+
+        self.push_scope(true);
+        for param in &func.arguments {
+            self.resolve_type(&param.ty);
+            self.declare(Namespace::Value, &param.name, DeclKind::Param, param.mutable, param.name_span);
+        }
+        self.resolve_block(&func.block);
+        self.pop_scope();
+    }
+
+    fn resolve_block(&mut self, block: &[ASTNode]) {
+        self.push_scope(false);
+        for statement in block {
+            self.resolve_statement(statement);
+        }
+        self.pop_scope();
+    }
+
+    fn resolve_statement(&mut self, node: &ASTNode) {
+        match node {
+            // The initializer cannot see the variable it initializes.
+            ASTNode::VarDecNode(var) => {
+                if let Some(ty) = &var.ty {
+                    self.resolve_type(ty);
+                }
+                if let Some(value) = &var.value {
+                    self.resolve_expr(value);
+                }
+                self.declare(Namespace::Value, &var.name, DeclKind::Local, var.mutable, var.name_span);
+            }
+
+            ASTNode::ConstDecl(constant) => {
+                self.resolve_type(&constant.ty);
+                self.resolve_expr(&constant.value);
+                self.declare(Namespace::Value, &constant.name, DeclKind::Const, false, constant.name_span);
+            }
+
+            // Declared first so the function can call itself.
+            ASTNode::FuncDef(func) => {
+                self.declare(Namespace::Value, &func.name, DeclKind::Function, false, func.name_span);
+                self.resolve_function(func);
+            }
+
+            ASTNode::TypeDecl(decl) => {
+                self.declare(Namespace::Type, &decl.name, DeclKind::Type, false, decl.name_span);
+                self.resolve_type(&decl.ty);
+            }
+
+            ASTNode::IfElseNode { condition, then_branch, elif_branch, else_branch, .. } => {
+                self.resolve_expr(condition);
+                self.resolve_block(then_branch);
+                for (condition, block) in elif_branch {
+                    self.resolve_expr(condition);
+                    self.resolve_block(block);
+                }
+                if let Some(block) = else_branch {
+                    self.resolve_block(block);
+                }
+            }
+
+            ASTNode::LoopNode { condition, block, .. } => {
+                self.resolve_expr(condition);
+                self.resolve_block(block);
+            }
+
+            ASTNode::ReturnNode(Some(value), _) => self.resolve_expr(value),
+
+            ASTNode::AssignNode { target, value, .. } => {
+                self.resolve_expr(target);
+                self.resolve_expr(value);
+            }
+
+            _ => self.resolve_expr(node),
+        }
+    }
+
+    fn resolve_expr(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Identifier(name, span) => self.lookup(Namespace::Value, name, *span, "value"),
+
+            ASTNode::FuncCall { name, name_span, arguments, .. } => {
+                self.lookup(Namespace::Value, name, *name_span, "function");
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+
+            ASTNode::UnaryOpNode { operand, .. }
+            | ASTNode::DerefNode { operand, .. }
+            | ASTNode::AddrOfNode { operand, .. } => self.resolve_expr(operand),
+
+            ASTNode::CastNode { value, ty, .. } => {
+                self.resolve_expr(value);
+                self.resolve_type(ty);
+            }
+
+            ASTNode::BinOpNode { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+
+            ASTNode::IndexNode { base, index, .. } => {
+                self.resolve_expr(base);
+                self.resolve_expr(index);
+            }
+
+            ASTNode::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+
+            ASTNode::ArrayRepeat { value, count, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(count);
+            }
+
+            _ => {}
+        }
+    }
+
+    fn resolve_type(&mut self, ty: &TypeExpr) {
+        match ty {
+            TypeExpr::Named(name, span) => self.lookup(Namespace::Type, name, *span, "type"),
+            TypeExpr::Pointer { pointee, .. } => self.resolve_type(pointee),
+            TypeExpr::Array { element, size, .. } => {
+                self.resolve_type(element);
+                self.resolve_expr(size);
+            }
+            TypeExpr::Int(..) | TypeExpr::Bool(_) => {}
+        }
+    }
+}
+
+// Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitute.min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+
+    return prev[b.len()];
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+// Location of a piece of source text. `line` and `column` are 1-based and
+// point at the first character, `start..end` is the byte range in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+
+    // Span covering everything from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        return Span {
+            end: other.end,
+            ..self
+        };
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+// Every file handed to the compiler, indexed by `Span::file`.
+pub struct SourceMap {
+    files: Vec<(String, String)>,
+}
+
+impl SourceMap {
+
+    pub fn new() -> Self {
+        return Self {
+            files: Vec::new(),
+        };
+    }
+
+    pub fn add(&mut self, name: &str, text: &str) -> usize {
+        self.files.push((name.to_string(), text.to_string()));
+        return self.files.len() - 1;
+    }
+
+    pub fn name(&self, file: usize) -> &str {
+        return &self.files[file].0;
+    }
+
+    pub fn text(&self, file: usize) -> &str {
+        return &self.files[file].1;
+    }
+
+    // Text of the 1-based `line` in `file`, without the line terminator.
+    pub fn line(&self, file: usize, line: usize) -> &str {
+        return self.text(file).lines().nth(line - 1).unwrap_or("");
+    }
+}
@@ -0,0 +1,945 @@
+use std::collections::HashMap;
+
+use crate::bigint::BigUint;
+use crate::consteval::{self, EvalResult, Value};
+use crate::diagnostic::Diagnostic;
+use crate::parser::{ASTNode, ConstDecl, Function, Item, Module, TypeExpr, VarDecl};
+use crate::resolve::{DeclId, DeclKind, Resolution};
+use crate::span::Span;
+use crate::types::{IntTy, Signature, Type};
+
+// Gives every expression and declaration in a resolved module a type and
+// checks that they fit together. There are no implicit conversions between
+// types, except that a `*mut T` may be used as a `*T`.
+//
+// Types flow down from what the context expects: an unsuffixed integer or
+// char literal takes the integer type expected of it, and an untyped `let`
+// takes the type of its initializer. A literal that nothing constrains is
+// an `i32`, or the first of `i64` and `u64` it fits in, a char literal is a
+// `u8` (`u32` past `'\u{ff}'`). A string literal is a NUL terminated array
+// of bytes, so `"hi"` is a `[u8; 3]`.
+//
+// The value of every constant is computed here too, since array lengths
+// depend on them.
+
+#[derive(Debug)]
+pub struct Typing {
+    // Type of every expression, keyed by its span.
+    pub exprs: HashMap<Span, Type>,
+    // Type of every variable, constant, parameter and type alias.
+    pub decls: HashMap<DeclId, Type>,
+    pub functions: HashMap<DeclId, Signature>,
+    // Value of every constant that could be evaluated.
+    pub consts: HashMap<DeclId, Value>,
+}
+
+// The function whose body is being checked.
+struct FnContext {
+    ret: Type,
+    ret_span: Option<Span>,
+}
+
+struct Checker<'m> {
+    resolution: &'m Resolution,
+    typing: Typing,
+    aliases: HashMap<DeclId, &'m TypeExpr>,
+    // Where each function in `typing.functions` is defined.
+    definitions: HashMap<DeclId, &'m Function>,
+    // Constants whose values have not been computed yet.
+    constants: HashMap<DeclId, &'m ConstDecl>,
+    // Aliases being expanded, to catch aliases defined in terms of
+    // themselves.
+    expanding: Vec<DeclId>,
+    // Constants whose types or values are being computed, likewise.
+    evaluating: Vec<DeclId>,
+    function: Option<FnContext>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+pub fn check<'m>(module: &'m Module, resolution: &'m Resolution) -> (Typing, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        resolution,
+        typing: Typing {
+            exprs: HashMap::new(),
+            decls: HashMap::new(),
+            functions: HashMap::new(),
+            consts: HashMap::new(),
+        },
+        aliases: HashMap::new(),
+        definitions: HashMap::new(),
+        constants: HashMap::new(),
+        expanding: Vec::new(),
+        evaluating: Vec::new(),
+        function: None,
+        diagnostics: Vec::new(),
+    };
+
+    // Aliases and constants can be used before they are declared, so all of
+    // them are known before any is expanded or evaluated.
+    for item in &module.items {
+        match item {
+            Item::Type(decl) => {
+                if let Some(id) = checker.decl_id(decl.name_span) {
+                    checker.aliases.insert(id, &decl.ty);
+                }
+            }
+            Item::Const(constant) => {
+                if let Some(id) = checker.decl_id(constant.name_span) {
+                    checker.constants.insert(id, constant);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Everything with a written type can be used anywhere, so those types
+    // come first.
+    for item in &module.items {
+        match item {
+            Item::Type(decl) => checker.alias(decl.name_span),
+            Item::Function(func) => checker.signature(func),
+            Item::Const(constant) => {
+                if let Some(id) = checker.decl_id(constant.name_span) {
+                    checker.const_type(id);
+                }
+            }
+            Item::Global(var) => {
+                if let Some(ty) = &var.ty {
+                    let ty = checker.lower(ty);
+                    checker.declare(var.name_span, ty);
+                }
+            }
+            Item::Error(_) => {}
+        }
+    }
+
+    // Initializers run in source order, so an untyped global is only known
+    // to the globals after it. Function bodies see all of them.
+    for item in &module.items {
+        match item {
+            Item::Const(constant) => checker.check_const(constant),
+            Item::Global(var) => {
+                let ty = var.ty.as_ref().map(|_| checker.declared(var.name_span));
+                checker.check_var(var, ty);
+            }
+            _ => {}
+        }
+    }
+
+    for item in &module.items {
+        if let Item::Function(func) = item {
+            checker.check_function(func);
+        }
+    }
+
+    return (checker.typing, checker.diagnostics);
+}
+
+fn mismatch(expected: &Type, found: &Type, span: Span) -> Diagnostic {
+    return Diagnostic::error("E0401", "mismatched types", span)
+        .with_label(format!("expected `{}`, found `{}`", expected, found));
+}
+
+// A call to `func` with the wrong number of arguments.
+fn arity_mismatch(func: &Function, signature: &Signature, arguments: &[ASTNode], span: Span) -> Diagnostic {
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let expected = func.arguments.len();
+
+    let mut diag = Diagnostic::error(
+        "E0415",
+        format!(
+            "function `{}` takes {} argument{} but {} {} supplied",
+            func.name, expected, plural(expected), arguments.len(), if arguments.len() == 1 { "was" } else { "were" },
+        ),
+        span,
+    );
+
+    if arguments.len() < expected {
+        let missing = &func.arguments[arguments.len()..];
+        let names: Vec<String> = missing.iter().map(|param| format!("`{}`", param.name)).collect();
+        diag = diag.with_label(format!("missing argument{} for {}", plural(missing.len()), names.join(", ")));
+    } else {
+        let extra = &arguments[expected..];
+        diag = diag.with_label(format!("expected {} argument{}", expected, plural(expected)))
+            .with_secondary(extra[0].span().to(extra[extra.len() - 1].span()), format!("unexpected argument{}", plural(extra.len())));
+    }
+
+    return diag.with_secondary(func.name_span, format!("`{}` is defined here", func.name))
+        .with_note(format!("`{}` has the signature `{}`", func.name, signature));
+}
+
+// Whether `node` is an integer or char literal without a suffix, possibly
+// under operators that keep its type open. Such an operand takes its type
+// from the other side of a binary operator.
+fn is_untyped_literal(node: &ASTNode) -> bool {
+    match node {
+        ASTNode::IntLiteral(_, None, _) | ASTNode::CharLiteral(..) => true,
+        ASTNode::UnaryOpNode { op, operand, .. } => op != "!" && is_untyped_literal(operand),
+        ASTNode::BinOpNode { op, left, right, .. } => {
+            is_arithmetic(op) && is_untyped_literal(left) && is_untyped_literal(right)
+        }
+        _ => false,
+    }
+}
+
+fn is_arithmetic(op: &str) -> bool {
+    matches!(op, "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^")
+}
+
+fn is_comparison(op: &str) -> bool {
+    matches!(op, "==" | "!=" | "<" | ">" | "<=" | ">=")
+}
+
+impl<'m> Checker<'m> {
+
+    fn decl_id(&self, name_span: Span) -> Option<DeclId> {
+        return self.resolution.declarations.get(&name_span).copied();
+    }
+
+    fn declare(&mut self, name_span: Span, ty: Type) {
+        if let Some(id) = self.decl_id(name_span) {
+            self.typing.decls.insert(id, ty);
+        }
+    }
+
+    // Type given to a declaration earlier on.
+    fn declared(&self, name_span: Span) -> Type {
+        return self.decl_id(name_span)
+            .and_then(|id| self.typing.decls.get(&id))
+            .cloned()
+            .unwrap_or(Type::Error);
+    }
+
+    fn lower(&mut self, ty: &TypeExpr) -> Type {
+        match ty {
+            TypeExpr::Int(ty, _) => Type::Int(*ty),
+            TypeExpr::Bool(_) => Type::Bool,
+            TypeExpr::Pointer { mutable, pointee, .. } => Type::Pointer {
+                mutable: *mutable,
+                pointee: Box::new(self.lower(pointee)),
+            },
+            TypeExpr::Array { element, size, .. } => {
+                let element = self.lower(element);
+                match self.array_len(size) {
+                    Some(len) => Type::Array { element: Box::new(element), len },
+                    None => Type::Error,
+                }
+            }
+            TypeExpr::Named(name, span) => match self.resolution.uses.get(span) {
+                Some(&id) => self.expand(id, name, *span),
+                None => Type::Error,
+            },
+        }
+    }
+
+    // Expands the alias `id`, used as `name` at `span`.
+    fn expand(&mut self, id: DeclId, name: &str, span: Span) -> Type {
+        if let Some(ty) = self.typing.decls.get(&id) {
+            return ty.clone();
+        }
+
+        if self.expanding.contains(&id) {
+            let decl = self.resolution.decl(id);
+            self.diagnostics.push(
+                Diagnostic::error("E0408", format!("type alias `{}` is defined in terms of itself", name), decl.span)
+                    .with_label("expanding this alias never ends")
+                    .with_secondary(span, format!("`{}` is used again here", name))
+                    .with_note("an alias only gives another name to an existing type"),
+            );
+            self.typing.decls.insert(id, Type::Error);
+            return Type::Error;
+        }
+
+        let ty = match self.aliases.get(&id) {
+            Some(&ty) => {
+                self.expanding.push(id);
+                let ty = self.lower(ty);
+                self.expanding.pop();
+                ty
+            }
+            None => Type::Error,
+        };
+
+        // A cycle through this alias may already have recorded an error.
+        return self.typing.decls.entry(id).or_insert(ty).clone();
+    }
+
+    fn alias(&mut self, name_span: Span) {
+        if let Some(id) = self.decl_id(name_span) {
+            let name = self.resolution.decl(id).name.clone();
+            self.expand(id, &name, name_span);
+        }
+    }
+
+    // Array lengths are constant expressions of any integer type.
+    fn array_len(&mut self, size: &ASTNode) -> Option<u64> {
+        let ty = self.expr(size, Some(&Type::Int(IntTy::U64)));
+        if !ty.is_int() {
+            if !ty.is_error() {
+                self.diagnostics.push(
+                    Diagnostic::error("E0409", "array length must be an integer", size.span())
+                        .with_label(format!("this is `{}`", ty)),
+                );
+            }
+            return None;
+        }
+
+        match consteval::eval(size, self) {
+            Ok(Value::Int(len)) if len >= 0 => return Some(len as u64),
+            Ok(value) => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0409", "array length cannot be negative", size.span())
+                        .with_label(format!("this is {}", value)),
+                );
+                return None;
+            }
+            Err(diag) => {
+                self.diagnostics.extend(diag);
+                return None;
+            }
+        }
+    }
+
+    // Type of the constant `id`, lowered on first use since an array length
+    // may name a constant declared after it.
+    fn const_type(&mut self, id: DeclId) -> Option<Type> {
+        if let Some(ty) = self.typing.decls.get(&id) {
+            return Some(ty.clone());
+        }
+
+        let constant = *self.constants.get(&id)?;
+        if self.evaluating.contains(&id) {
+            return None;
+        }
+
+        self.evaluating.push(id);
+        let ty = self.lower(&constant.ty);
+        self.evaluating.pop();
+
+        self.typing.decls.insert(id, ty.clone());
+        return Some(ty);
+    }
+
+    fn check_const(&mut self, constant: &'m ConstDecl) {
+        if let Some(id) = self.decl_id(constant.name_span) {
+            self.constants.entry(id).or_insert(constant);
+            let _ = self.const_value(id, constant.name_span);
+        }
+    }
+
+    // Checks the initializer of the constant `id`, used at `span`, and
+    // computes its value. Problems are reported once, with the constant.
+    fn const_value(&mut self, id: DeclId, span: Span) -> EvalResult {
+        if let Some(&value) = self.typing.consts.get(&id) {
+            return Ok(value);
+        }
+
+        if self.evaluating.contains(&id) {
+            let decl = self.resolution.decl(id);
+            return Err(Some(consteval::cycle(&decl.name, decl.span, span)));
+        }
+
+        let ty = self.const_type(id).ok_or(None)?;
+        let constant = self.constants.remove(&id).ok_or(None)?;
+
+        self.evaluating.push(id);
+        let reported = self.diagnostics.len();
+        self.check_expr(&constant.value, &ty, Some((constant.ty.span(), "expected due to this type")));
+
+        // Evaluating an initializer with type errors would only add noise.
+        let result = if ty.is_error() || self.diagnostics[reported..].iter().any(|diag| diag.is_error()) {
+            Err(None)
+        } else {
+            consteval::eval(&constant.value, self)
+        };
+        self.evaluating.pop();
+
+        match result {
+            Ok(value) => {
+                self.typing.consts.insert(id, value);
+                return Ok(value);
+            }
+            Err(diag) => {
+                self.diagnostics.extend(diag);
+                return Err(None);
+            }
+        }
+    }
+
+    fn signature(&mut self, func: &'m Function) {
+        let mut params = Vec::new();
+        for param in &func.arguments {
+            let ty = self.lower(&param.ty);
+            self.declare(param.name_span, ty.clone());
+            params.push(ty);
+        }
+
+        let ret = match &func.ret_type {
+            Some(ty) => self.lower(ty),
+            None => Type::Unit,
+        };
+
+        if let Some(id) = self.decl_id(func.name_span) {
+            self.typing.functions.insert(id, Signature { params, ret });
+            self.definitions.insert(id, func);
+        }
+    }
+
+    fn check_function(&mut self, func: &'m Function) {
+        let ret = self.decl_id(func.name_span)
+            .and_then(|id| self.typing.functions.get(&id))
+            .map_or(Type::Error, |sig| sig.ret.clone());
+
+        let outer = self.function.replace(FnContext {
+            ret,
+            ret_span: func.ret_type.as_ref().map(|ty| ty.span()),
+        });
+        self.check_block(&func.block);
+        self.function = outer;
+    }
+
+    fn check_block(&mut self, block: &'m [ASTNode]) {
+        for statement in block {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, node: &'m ASTNode) {
+        match node {
+            ASTNode::VarDecNode(var) => {
+                let ty = var.ty.as_ref().map(|annotation| self.lower(annotation));
+                self.check_var(var, ty);
+            }
+
+            ASTNode::ConstDecl(constant) => self.check_const(constant),
+
+            ASTNode::FuncDef(func) => {
+                self.signature(func);
+                self.check_function(func);
+            }
+
+            ASTNode::TypeDecl(decl) => {
+                if let Some(id) = self.decl_id(decl.name_span) {
+                    self.aliases.insert(id, &decl.ty);
+                }
+                self.alias(decl.name_span);
+            }
+
+            ASTNode::IfElseNode { condition, then_branch, elif_branch, else_branch, .. } => {
+                self.check_expr(condition, &Type::Bool, None);
+                self.check_block(then_branch);
+                for (condition, block) in elif_branch {
+                    self.check_expr(condition, &Type::Bool, None);
+                    self.check_block(block);
+                }
+                if let Some(block) = else_branch {
+                    self.check_block(block);
+                }
+            }
+
+            ASTNode::LoopNode { condition, block, .. } => {
+                self.check_expr(condition, &Type::Bool, None);
+                self.check_block(block);
+            }
+
+            ASTNode::ReturnNode(Some(value), _) => self.check_return(value),
+
+            ASTNode::AssignNode { target, value, .. } => {
+                let ty = self.expr(target, None);
+                self.check_expr(value, &ty, None);
+            }
+
+            ASTNode::ReturnNode(None, _) | ASTNode::BreakNode(_) | ASTNode::Error(_) => {}
+
+            _ => {
+                self.expr(node, None);
+            }
+        }
+    }
+
+    // Checks the value of `var` against `ty`, its written type, or gives it
+    // the type of its value. A variable with neither has no type.
+    fn check_var(&mut self, var: &VarDecl, ty: Option<Type>) {
+        let ty = match (ty, &var.value) {
+            (Some(ty), Some(value)) => {
+                let origin = var.ty.as_ref().map(|annotation| (annotation.span(), "expected due to this type"));
+                self.check_expr(value, &ty, origin);
+                ty
+            }
+            (Some(ty), None) => ty,
+            (None, Some(value)) => self.expr(value, None),
+            (None, None) => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0407", format!("type annotations needed for `{}`", var.name), var.name_span)
+                        .with_label(format!("the type of `{}` is not known", var.name))
+                        .with_note(format!("a variable declared without a value needs a type, like `let {}: u32;`", var.name)),
+                );
+                Type::Error
+            }
+        };
+        self.declare(var.name_span, ty);
+    }
+
+    // Whether a `return` should have a value at all is checked with the
+    // rest of the control flow.
+    fn check_return(&mut self, value: &ASTNode) {
+        match &self.function {
+            Some(FnContext { ret, ret_span: Some(ret_span) }) => {
+                let (ret, ret_span) = (ret.clone(), *ret_span);
+                self.check_expr(value, &ret, Some((ret_span, "expected because of this return type")));
+            }
+            _ => {
+                self.expr(value, None);
+            }
+        }
+    }
+
+    // Checks that `node` has type `expected`. `origin` points at whatever
+    // made that type expected.
+    fn check_expr(&mut self, node: &ASTNode, expected: &Type, origin: Option<(Span, &str)>) {
+        let found = self.expr(node, Some(expected));
+        if found.coerces_to(expected) {
+            return;
+        }
+
+        let mut diag = mismatch(expected, &found, node.span());
+        if let Some((span, message)) = origin {
+            diag = diag.with_secondary(span, message);
+        }
+        if let (Type::Pointer { mutable: false, .. }, Type::Pointer { mutable: true, .. }) = (&found, expected) {
+            diag = diag.with_note("a `*T` cannot be used as a `*mut T`, take the address with `&mut` instead");
+        }
+        self.diagnostics.push(diag);
+    }
+
+    // Infers the type of `node`, using `expected` only to type literals.
+    fn expr(&mut self, node: &ASTNode, expected: Option<&Type>) -> Type {
+        let ty = self.infer(node, expected);
+        self.typing.exprs.insert(node.span(), ty.clone());
+        return ty;
+    }
+
+    fn infer(&mut self, node: &ASTNode, expected: Option<&Type>) -> Type {
+        match node {
+            ASTNode::IntLiteral(value, Some(ty), span) => {
+                self.int_literal(value, false, format!("{}{}", value, ty), *span, Some(&Type::Int(*ty)), *ty)
+            }
+
+            ASTNode::IntLiteral(value, None, span) => {
+                self.int_literal(value, false, value.to_string(), *span, expected, IntTy::I32)
+            }
+
+            ASTNode::CharLiteral(c, span) => {
+                let default = if (*c as u32) <= 0xFF { IntTy::U8 } else { IntTy::U32 };
+                let shown = format!("'{}'", c.escape_debug());
+                self.int_literal(&BigUint::from_u128(*c as u128), false, shown, *span, expected, default)
+            }
+
+            ASTNode::FloatLiteral(_, span) => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0410", "floating point numbers are not supported", *span)
+                        .with_label("there is no floating point type")
+                        .with_note("Zerl only has the integer types `u8`..`u64` and `i8`..`i64`"),
+                );
+                Type::Error
+            }
+
+            ASTNode::BoolNode(..) => Type::Bool,
+
+            ASTNode::StrLiteral(text, _) => Type::Array {
+                element: Box::new(Type::Int(IntTy::U8)),
+                len: text.len() as u64 + 1,
+            },
+
+            ASTNode::Identifier(name, span) => self.value(name, *span),
+
+            ASTNode::FuncCall { name, name_span, arguments, span } => self.call(name, *name_span, arguments, *span),
+
+            ASTNode::UnaryOpNode { op, operand, span } => self.unary(op, operand, *span, expected),
+
+            ASTNode::BinOpNode { op, left, right, span } => self.binary(op, left, right, *span, expected),
+
+            ASTNode::CastNode { value, ty, span } => self.cast(value, ty, *span),
+
+            ASTNode::DerefNode { operand, span } => {
+                let ty = self.expr(operand, None);
+                match ty {
+                    Type::Pointer { pointee, .. } => *pointee,
+                    Type::Error => Type::Error,
+                    _ => {
+                        self.diagnostics.push(
+                            Diagnostic::error("E0404", format!("type `{}` cannot be dereferenced", ty), *span)
+                                .with_label("only pointers can be dereferenced")
+                                .with_secondary(operand.span(), format!("this is `{}`", ty)),
+                        );
+                        Type::Error
+                    }
+                }
+            }
+
+            ASTNode::AddrOfNode { mutable, operand, span } => {
+                if !operand.is_place() {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0412", "cannot take the address of a temporary value", *span)
+                            .with_label("this value is not stored anywhere")
+                            .with_note("only variables, dereferences and array elements have an address"),
+                    );
+                }
+                let hint = match expected {
+                    Some(Type::Pointer { pointee, .. }) => Some(pointee.as_ref()),
+                    _ => None,
+                };
+                let pointee = self.expr(operand, hint);
+                Type::Pointer { mutable: *mutable, pointee: Box::new(pointee) }
+            }
+
+            ASTNode::ArrayLiteral { elements, span } => self.array_literal(elements, *span, expected),
+
+            ASTNode::ArrayRepeat { value, count, .. } => {
+                let hint = match expected {
+                    Some(Type::Array { element, .. }) => Some(element.as_ref()),
+                    _ => None,
+                };
+                let element = self.expr(value, hint);
+                match self.array_len(count) {
+                    Some(len) if !element.is_error() => Type::Array { element: Box::new(element), len },
+                    _ => Type::Error,
+                }
+            }
+
+            ASTNode::IndexNode { base, index, span } => self.index(base, index, *span),
+
+            _ => Type::Error,
+        }
+    }
+
+    // Gives a literal without suffix the integer type expected of it, or
+    // `default`, and checks that the value fits. A suffixed literal is
+    // expected to have its suffix's type. `negated` literals may be one past
+    // a signed maximum. `shown` is the literal as written, for the error.
+    fn int_literal(&mut self, value: &BigUint, negated: bool, shown: String, span: Span, expected: Option<&Type>, default: IntTy) -> Type {
+        let fits = |ty: IntTy| {
+            let max = if negated && ty.is_signed() { ty.max() + 1 } else { ty.max() };
+            *value <= BigUint::from_u128(max)
+        };
+
+        let ty = match expected {
+            Some(Type::Int(ty)) => *ty,
+            _ => match [default, IntTy::I64, IntTy::U64].into_iter().find(|ty| fits(*ty)) {
+                Some(ty) => ty,
+                None => {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0406", "integer literal is too large", span)
+                            .with_label(format!("`{}` does not fit in any integer type", shown))
+                            .with_note(format!("the largest integer type is `u64`, whose maximum value is {}", u64::MAX)),
+                    );
+                    return Type::Int(IntTy::U64);
+                }
+            },
+        };
+
+        // Negating an unsigned literal is reported by the operator.
+        if !fits(ty) && (!negated || ty.is_signed()) {
+            let min = if ty.is_signed() { format!("-{}", ty.max() + 1) } else { "0".to_string() };
+            self.diagnostics.push(
+                Diagnostic::error("E0406", format!("literal out of range for `{}`", ty), span)
+                    .with_label(format!("`{}` does not fit in `{}`", shown, ty))
+                    .with_note(format!("the range of `{}` is {}..={}", ty, min, ty.max())),
+            );
+        }
+
+        return Type::Int(ty);
+    }
+
+    fn value(&mut self, name: &str, span: Span) -> Type {
+        let id = match self.resolution.uses.get(&span) {
+            Some(&id) => id,
+            None => return Type::Error,
+        };
+
+        if let Some(ty) = self.typing.decls.get(&id) {
+            return ty.clone();
+        }
+        if let Some(ty) = self.const_type(id) {
+            return ty;
+        }
+
+        let decl = self.resolution.decl(id);
+        let diag = if decl.kind == DeclKind::Function {
+            Diagnostic::error("E0405", format!("expected value, found function `{}`", name), span)
+                .with_label("not a value")
+                .with_note(format!("to call it, write `{}(...)`", name))
+        } else {
+            Diagnostic::error("E0407", format!("type of `{}` is not known yet", name), span)
+                .with_label("used before its initializer is checked")
+                .with_secondary(decl.span, format!("consider giving `{}` a type", name))
+        };
+        self.diagnostics.push(diag);
+        return Type::Error;
+    }
+
+    // Checks a call against the signature of the function it names, which
+    // may be defined anywhere in the file.
+    fn call(&mut self, name: &str, name_span: Span, arguments: &[ASTNode], span: Span) -> Type {
+        let id = self.resolution.uses.get(&name_span).copied();
+        let function = id.and_then(|id| Some((self.typing.functions.get(&id)?.clone(), *self.definitions.get(&id)?)));
+
+        let (signature, definition) = match function {
+            Some(function) => function,
+            None => {
+                if let Some(id) = id {
+                    let decl = self.resolution.decl(id);
+                    self.diagnostics.push(
+                        Diagnostic::error("E0405", format!("`{}` is not a function", name), name_span)
+                            .with_label("called here")
+                            .with_secondary(decl.span, format!("`{}` is a {}", name, decl.kind.describe())),
+                    );
+                }
+                for argument in arguments {
+                    self.expr(argument, None);
+                }
+                return Type::Error;
+            }
+        };
+
+        if arguments.len() != signature.params.len() {
+            self.diagnostics.push(arity_mismatch(definition, &signature, arguments, span));
+        }
+
+        for (i, argument) in arguments.iter().enumerate() {
+            match (signature.params.get(i), definition.arguments.get(i)) {
+                (Some(ty), Some(param)) => {
+                    let origin = format!("parameter `{}` of `{}` is declared here", param.name, name);
+                    self.check_expr(argument, ty, Some((param.span, &origin)));
+                }
+                _ => {
+                    self.expr(argument, None);
+                }
+            }
+        }
+
+        return signature.ret;
+    }
+
+    fn unary(&mut self, op: &str, operand: &ASTNode, span: Span, expected: Option<&Type>) -> Type {
+        let ty = match operand {
+            ASTNode::IntLiteral(value, suffix, literal_span) if op == "-" => {
+                let suffixed = suffix.map(Type::Int);
+                let shown = format!("-{}{}", value, suffix.map_or(String::new(), |ty| ty.to_string()));
+                let ty = self.int_literal(value, true, shown, *literal_span, suffixed.as_ref().or(expected), IntTy::I32);
+                self.typing.exprs.insert(*literal_span, ty.clone());
+                ty
+            }
+            _ => self.expr(operand, expected),
+        };
+
+        let valid = match op {
+            "-" => matches!(ty, Type::Int(int) if int.is_signed()),
+            "~" => ty.is_int(),
+            _ => ty == Type::Bool,
+        };
+        if valid || ty.is_error() {
+            return ty;
+        }
+
+        let diag = Diagnostic::error("E0403", format!("cannot apply unary operator `{}` to type `{}`", op, ty), span)
+            .with_label(format!("this is `{}`", ty));
+        let diag = match op {
+            "-" if ty.is_int() => diag.with_note(format!("`{}` is unsigned, only signed integers can be negated", ty)),
+            "-" | "~" => diag.with_note(format!("`{}` only applies to integers", op)),
+            _ if ty.is_int() => diag.with_note("`!` only applies to `bool`, use `~` to flip the bits of an integer"),
+            _ => diag.with_note("`!` only applies to `bool`"),
+        };
+        self.diagnostics.push(diag);
+        return Type::Error;
+    }
+
+    fn binary(&mut self, op: &str, left: &ASTNode, right: &ASTNode, span: Span, expected: Option<&Type>) -> Type {
+        if op == "and" || op == "or" {
+            self.check_expr(left, &Type::Bool, None);
+            self.check_expr(right, &Type::Bool, None);
+            return Type::Bool;
+        }
+
+        // The amount of a shift can be any integer type.
+        if op == "<<" || op == ">>" {
+            let ty = self.expr(left, expected);
+            let amount = self.expr(right, None);
+            for (operand, ty) in [(left, &ty), (right, &amount)] {
+                if !ty.is_int() && !ty.is_error() {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0402", format!("cannot shift by `{}`", ty), span)
+                            .with_label(format!("`{}` only applies to integers", op))
+                            .with_secondary(operand.span(), format!("this is `{}`", ty)),
+                    );
+                }
+            }
+            return if ty.is_int() { ty } else { Type::Error };
+        }
+
+        let comparison = is_comparison(op);
+        let hint = if comparison { None } else { expected };
+
+        let (left_ty, right_ty) = if is_untyped_literal(left) && !is_untyped_literal(right) {
+            let right_ty = self.expr(right, hint);
+            (self.expr(left, Some(&right_ty)), right_ty)
+        } else {
+            let left_ty = self.expr(left, hint);
+            let right_ty = self.expr(right, Some(&left_ty));
+            (left_ty, right_ty)
+        };
+
+        let result = if comparison { Type::Bool } else { left_ty.clone() };
+        if left_ty.is_error() || right_ty.is_error() {
+            return if comparison { Type::Bool } else { Type::Error };
+        }
+
+        let valid = match op {
+            "==" | "!=" => matches!(left_ty, Type::Int(_) | Type::Bool | Type::Pointer { .. }),
+            _ => left_ty.is_int(),
+        };
+        if !valid {
+            let what = if op == "==" || op == "!=" { "integers, `bool` and pointers" } else { "integers" };
+            let mut diag = Diagnostic::error("E0402", format!("cannot apply binary operator `{}` to type `{}`", op, left_ty), span)
+                .with_label(format!("`{}` only applies to {}", op, what));
+            if left_ty == Type::Bool && matches!(op, "&" | "|") {
+                let logical = if op == "&" { "and" } else { "or" };
+                diag = diag.with_note(format!("use `{}` to combine `bool` values", logical));
+            }
+            self.diagnostics.push(diag);
+            return if comparison { Type::Bool } else { Type::Error };
+        }
+
+        if !left_ty.coerces_to(&right_ty) && !right_ty.coerces_to(&left_ty) {
+            let mut diag = Diagnostic::error("E0402", format!("mismatched operand types for `{}`", op), right.span())
+                .with_label(format!("expected `{}`, found `{}`", left_ty, right_ty))
+                .with_secondary(left.span(), format!("this is `{}`", left_ty));
+            if left_ty.is_int() && right_ty.is_int() {
+                diag = diag.with_note("integers of different widths are never converted implicitly");
+            }
+            self.diagnostics.push(diag);
+            return if comparison { Type::Bool } else { Type::Error };
+        }
+
+        return result;
+    }
+
+    // Integers, `bool` and pointers can be cast to integers, and integers
+    // and pointers to pointers.
+    fn cast(&mut self, value: &ASTNode, ty: &TypeExpr, span: Span) -> Type {
+        let target = self.lower(ty);
+        // An untyped literal cast to a pointer is an address.
+        let hint = match target {
+            Type::Pointer { .. } => Some(Type::Int(IntTy::U64)),
+            _ => None,
+        };
+        let source = self.expr(value, hint.as_ref());
+
+        let valid = match (&source, &target) {
+            (Type::Int(_) | Type::Bool | Type::Pointer { .. }, Type::Int(_)) => true,
+            (Type::Int(_) | Type::Pointer { .. }, Type::Pointer { .. }) => true,
+            _ => source == target || source.is_error() || target.is_error(),
+        };
+        if valid {
+            return target;
+        }
+
+        let diag = Diagnostic::error("E0414", format!("cannot cast `{}` as `{}`", source, target), span)
+            .with_label("invalid cast")
+            .with_secondary(value.span(), format!("this is `{}`", source));
+        let diag = match (&source, &target) {
+            (Type::Int(_), Type::Bool) => diag.with_note("compare with zero instead, like `x != 0`"),
+            _ => diag.with_note("only integers, `bool` and pointers can be cast, and only integers and pointers are cast to"),
+        };
+        self.diagnostics.push(diag);
+        return target;
+    }
+
+    fn array_literal(&mut self, elements: &[ASTNode], span: Span, expected: Option<&Type>) -> Type {
+        let hint = match expected {
+            Some(Type::Array { element, .. }) => Some(element.as_ref().clone()),
+            _ => None,
+        };
+
+        let element = match (elements.first(), hint) {
+            (Some(first), hint) => self.expr(first, hint.as_ref()),
+            (None, Some(hint)) => hint,
+            (None, None) => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0407", "cannot infer the type of an empty array", span)
+                        .with_label("element type unknown")
+                        .with_note("give the variable a type, like `[u8; 0]`"),
+                );
+                return Type::Error;
+            }
+        };
+
+        for other in elements.iter().skip(1) {
+            self.check_expr(other, &element, Some((elements[0].span(), "the first element sets the element type")));
+        }
+
+        if element.is_error() {
+            return Type::Error;
+        }
+        return Type::Array { element: Box::new(element), len: elements.len() as u64 };
+    }
+
+    fn index(&mut self, base: &ASTNode, index: &ASTNode, span: Span) -> Type {
+        let base_ty = self.expr(base, None);
+        let index_ty = self.expr(index, None);
+
+        if !index_ty.is_int() && !index_ty.is_error() {
+            self.diagnostics.push(
+                Diagnostic::error("E0401", "array index must be an integer", index.span())
+                    .with_label(format!("found `{}`", index_ty)),
+            );
+        }
+
+        match base_ty {
+            Type::Array { element, len } => {
+                // Indices that are not constant are checked at run time.
+                let constant = if index_ty.is_int() { consteval::eval(index, self).ok() } else { None };
+                if let Some(Value::Int(value)) = constant {
+                    if value < 0 || value >= len as i128 {
+                        self.diagnostics.push(
+                            Diagnostic::error("E0413", "index out of bounds", index.span())
+                                .with_label(format!("the length is {} but the index is {}", len, value))
+                                .with_secondary(base.span(), format!("this is `[{}; {}]`", element, len)),
+                        );
+                    }
+                }
+                *element
+            }
+            Type::Pointer { pointee, .. } => *pointee,
+            Type::Error => Type::Error,
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error("E0404", format!("cannot index into a value of type `{}`", base_ty), span)
+                        .with_label("only arrays and pointers can be indexed")
+                        .with_secondary(base.span(), format!("this is `{}`", base_ty)),
+                );
+                Type::Error
+            }
+        }
+    }
+}
+
+impl<'m> consteval::Env for Checker<'m> {
+
+    fn type_of(&self, span: Span) -> Type {
+        return self.typing.exprs.get(&span).cloned().unwrap_or(Type::Error);
+    }
+
+    fn constant(&mut self, span: Span) -> Option<EvalResult> {
+        // A name the type checker already rejected.
+        if self.type_of(span).is_error() {
+            return Some(Err(None));
+        }
+
+        let id = *self.resolution.uses.get(&span)?;
+        if self.resolution.decl(id).kind != DeclKind::Const {
+            return None;
+        }
+        return Some(self.const_value(id, span));
+    }
+}
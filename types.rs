@@ -0,0 +1,140 @@
+use std::fmt;
+
+// Fixed width integer types. These double as literal suffixes (`10u8`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntTy {
+    U8, U16, U32, U64,
+    I8, I16, I32, I64,
+}
+
+impl IntTy {
+
+    pub fn from_name(name: &str) -> Option<IntTy> {
+        match name {
+            "u8" => Some(IntTy::U8),
+            "u16" => Some(IntTy::U16),
+            "u32" => Some(IntTy::U32),
+            "u64" => Some(IntTy::U64),
+            "i8" => Some(IntTy::I8),
+            "i16" => Some(IntTy::I16),
+            "i32" => Some(IntTy::I32),
+            "i64" => Some(IntTy::I64),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IntTy::U8 => "u8",
+            IntTy::U16 => "u16",
+            IntTy::U32 => "u32",
+            IntTy::U64 => "u64",
+            IntTy::I8 => "i8",
+            IntTy::I16 => "i16",
+            IntTy::I32 => "i32",
+            IntTy::I64 => "i64",
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            IntTy::U8 | IntTy::I8 => 8,
+            IntTy::U16 | IntTy::I16 => 16,
+            IntTy::U32 | IntTy::I32 => 32,
+            IntTy::U64 | IntTy::I64 => 64,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntTy::I8 | IntTy::I16 | IntTy::I32 | IntTy::I64)
+    }
+
+    // Largest value of the type.
+    pub fn max(self) -> u128 {
+        if self.is_signed() {
+            return (1u128 << (self.bits() - 1)) - 1;
+        }
+        return (1u128 << self.bits()) - 1;
+    }
+}
+
+impl fmt::Display for IntTy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+// A type as the checker sees it: aliases are expanded and array lengths are
+// known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int(IntTy),
+    Bool,
+    Pointer {
+        mutable: bool,
+        pointee: Box<Type>,
+    },
+    Array {
+        element: Box<Type>,
+        len: u64,
+    },
+    // What calling a function without a return type produces.
+    Unit,
+    // The type of anything that already failed to check. It is compatible
+    // with everything so that one mistake is reported once.
+    Error,
+}
+
+impl Type {
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Type::Error)
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Type::Int(_))
+    }
+
+    // Whether a value of this type can be used where `expected` is wanted.
+    // Besides identical types, a `*mut T` may be used as a `*T`.
+    pub fn coerces_to(&self, expected: &Type) -> bool {
+        match (self, expected) {
+            (Type::Error, _) | (_, Type::Error) => true,
+            (Type::Pointer { mutable: from_mut, pointee: from }, Type::Pointer { mutable: to_mut, pointee: to }) => {
+                (*from_mut || !*to_mut) && from == to
+            }
+            _ => self == expected,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int(ty) => write!(f, "{}", ty),
+            Type::Bool => write!(f, "bool"),
+            Type::Pointer { mutable: true, pointee } => write!(f, "*mut {}", pointee),
+            Type::Pointer { mutable: false, pointee } => write!(f, "*{}", pointee),
+            Type::Array { element, len } => write!(f, "[{}; {}]", element, len),
+            Type::Unit => write!(f, "()"),
+            Type::Error => write!(f, "{{unknown}}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(|param| param.to_string()).collect();
+        write!(f, "fn({})", params.join(", "))?;
+        if self.ret != Type::Unit {
+            write!(f, " -> {}", self.ret)?;
+        }
+        return Ok(());
+    }
+}